@@ -0,0 +1,80 @@
+use adventofcode2021::intcode::{IntcodeMachine, Int, WideMachine, Word};
+use adventofcode2021::intcode::compile::compile;
+use adventofcode2021::intcode::load::load_file;
+use num_bigint::BigInt;
+use std::time::{Duration, Instant};
+
+// Times a program with and without the decode cache, with ordinary and
+// big integer words:
+//
+//   cargo run --release --example intcode_bench [program [input...]]
+//
+// Without a program it counts the primes below 3000 the slow way.
+
+const PRIMES: &str = r#"
+    fn main() {
+        var n = input();
+        var count = 0;
+        var i = 2;
+        while i < n {
+            var prime = 1;
+            var j = 2;
+            while prime && j * j <= i {
+                var k = j;
+                while k < i { k = k + j; }
+                if k == i { prime = 0; }
+                j = j + 1;
+            }
+            count = count + prime;
+            i = i + 1;
+        }
+        output(count);
+    }
+"#;
+
+fn time<W: Word>(program: &[Int], inputs: &[Int], cached: bool) -> (Duration, u64, Vec<W>) {
+    let mut best = None;
+    let mut result = (0, Vec::new());
+    for _ in 0..3 {
+        let mut machine: WideMachine<W> = IntcodeMachine::wide(program);
+        machine.set_decode_cache(cached);
+        for &val in inputs {
+            machine.send_input(W::from_int(val));
+        }
+        let start = Instant::now();
+        if let Err(e) = machine.run_until_halt() {
+            eprintln!("Stopped: {}", e);
+        }
+        let elapsed = start.elapsed();
+        best = Some(best.map_or(elapsed, |b: Duration| b.min(elapsed)));
+        result = (machine.steps(), machine.take_outputs());
+    }
+    (best.unwrap(), result.0, result.1)
+}
+
+fn report<W: Word>(name: &str, program: &[Int], inputs: &[Int]) {
+    let (plain, steps, outputs) = time::<W>(program, inputs, false);
+    let (cached, _, cached_outputs) = time::<W>(program, inputs, true);
+    assert_eq!(outputs, cached_outputs, "outputs differ with the cache");
+    let rate = |d: Duration| steps as f64 / d.as_secs_f64() / 1e6;
+    println!("{:8} {:>10} steps  plain {:8.2?} ({:6.1} M/s)  cached {:8.2?} ({:6.1} M/s)  x{:.2}",
+             name, steps, plain, rate(plain), cached, rate(cached),
+             plain.as_secs_f64() / cached.as_secs_f64());
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (program, inputs) = match args.split_first() {
+        Some((filename, inputs)) => {
+            let program = load_file(filename).unwrap_or_else(|e| {
+                eprintln!("Error loading {}: {}", filename, e);
+                std::process::exit(1);
+            });
+            let inputs = inputs.iter().map(|s| s.parse().expect("bad input")).collect();
+            (program, inputs)
+        }
+        None => (compile(PRIMES).unwrap(), vec![3000]),
+    };
+    report::<Int>("Int", &program, &inputs);
+    report::<BigInt>("BigInt", &program, &inputs);
+}
@@ -0,0 +1,506 @@
+use adventofcode2021::intcode::{IntcodeMachine, Int, Event, Watch};
+use adventofcode2021::intcode::asm::assemble;
+use adventofcode2021::intcode::disasm::decode_line;
+use adventofcode2021::intcode::load::load_file;
+use adventofcode2021::intcode::decompile::decompile;
+use adventofcode2021::intcode::trace::{Profile, SharedTracer, TraceWriter};
+use adventofcode2021::intcode::coverage::Coverage;
+use std::io::{self, Write, BufRead};
+use std::sync::{Arc, Mutex};
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+
+const HELP: &str = "\
+Commands:
+  s, step [n]         Execute n instructions (default 1)
+  c, continue         Run until a breakpoint, watchpoint, input needed or halt
+  bs, back [n]        Step back n instructions (default 1; needs record)
+  rewind <step>       Go back to a step number (needs record)
+  record [n]|off      Record up to n steps (default all) so they can be undone
+  b, break <addr>     Set a breakpoint
+  d, delete <addr>    Remove a breakpoint or watchpoint
+  w, watch <addr> [r|w|rw]
+                      Watch reads and/or writes of an address (default rw)
+  l, list             List breakpoints and watchpoints
+  x <addr> [n]        Examine n words of memory (default 8)
+  set <addr> <val>    Modify memory
+  r, regs             Show registers
+  pc <val>            Set pc
+  rb <val>            Set rel_base
+  in <val>...         Queue numeric input
+  ins <text>          Queue a line of ASCII input
+  out                 Show and clear outputs
+  dis [addr] [n]      Disassemble n instructions (default around pc)
+  trace <file>|off    Write an instruction trace to a file, or stop
+  profile on|off|[n]  Start or stop profiling, or show the n hottest
+                      instructions and loops (default 10)
+  coverage on|off|[file]
+                      Start or stop recording coverage, or show (or write)
+                      what has been executed, with branch directions
+  cfg <file>          Write the control flow graph from pc to a DOT file
+  decompile [file]    Show (or write) pseudocode for the code from pc
+  history             Show command history
+  !<n>                Repeat command n from the history
+  q, quit             Exit
+An empty line repeats the previous command.";
+
+struct LineEditor {
+    history: Vec<String>,
+    tty: bool,
+}
+
+impl LineEditor {
+    fn new() -> LineEditor {
+        LineEditor {
+            history: Vec::new(),
+            tty: termion::is_tty(&io::stdin()),
+        }
+    }
+
+    fn read_line(&mut self, prompt: &str) -> Option<String> {
+        let line = if self.tty {
+            self.read_line_raw(prompt).ok()?
+        } else {
+            print!("{}", prompt);
+            io::stdout().flush().ok()?;
+            let mut s = String::new();
+            if BufRead::read_line(&mut io::stdin().lock(), &mut s).ok()? == 0 {
+                return None;
+            }
+            Some(s.trim().to_string())
+        };
+        if let Some(line) = &line {
+            if !line.is_empty() && self.history.last() != Some(line) {
+                self.history.push(line.clone());
+            }
+        }
+        line
+    }
+
+    // Minimal line editing, with up/down to move through the history.
+    fn read_line_raw(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let mut stdout = io::stdout().into_raw_mode()?;
+        let mut line: Vec<char> = Vec::new();
+        let mut cursor = 0;
+        let mut hist_pos = self.history.len();
+        write!(stdout, "{}", prompt)?;
+        stdout.flush()?;
+        for key in io::stdin().keys() {
+            match key? {
+                Key::Char('\n') => break,
+                Key::Char(c) => {
+                    line.insert(cursor, c);
+                    cursor += 1;
+                }
+                Key::Backspace if cursor > 0 => {
+                    cursor -= 1;
+                    line.remove(cursor);
+                }
+                Key::Delete if cursor < line.len() => {
+                    line.remove(cursor);
+                }
+                Key::Left if cursor > 0 => cursor -= 1,
+                Key::Right if cursor < line.len() => cursor += 1,
+                Key::Home | Key::Ctrl('a') => cursor = 0,
+                Key::End | Key::Ctrl('e') => cursor = line.len(),
+                Key::Up if hist_pos > 0 => {
+                    hist_pos -= 1;
+                    line = self.history[hist_pos].chars().collect();
+                    cursor = line.len();
+                }
+                Key::Down if hist_pos < self.history.len() => {
+                    hist_pos += 1;
+                    line = self.history.get(hist_pos)
+                                       .map(|s| s.chars().collect())
+                                       .unwrap_or_default();
+                    cursor = line.len();
+                }
+                Key::Ctrl('c') => {
+                    line.clear();
+                    cursor = 0;
+                }
+                Key::Ctrl('d') if line.is_empty() => {
+                    write!(stdout, "\r\n")?;
+                    return Ok(None);
+                }
+                _ => {}
+            }
+            let text: String = line.iter().collect();
+            write!(stdout, "\r{}{}{}", termion::clear::CurrentLine, prompt, text)?;
+            if cursor < line.len() {
+                write!(stdout, "{}", termion::cursor::Left((line.len() - cursor) as u16))?;
+            }
+            stdout.flush()?;
+        }
+        write!(stdout, "\r\n")?;
+        Ok(Some(line.into_iter().collect::<String>().trim().to_string()))
+    }
+}
+
+struct Debugger {
+    machine: IntcodeMachine,
+    // The program as loaded, which coverage reports list
+    program: Vec<Int>,
+    outputs: Vec<Int>,
+    // Tracers slow every step, so these are only attached when wanted.
+    profile: Option<Arc<Mutex<Profile>>>,
+    coverage: Option<Arc<Mutex<Coverage>>>,
+    trace: Option<SharedTracer>,
+}
+
+fn parse_num<T: std::str::FromStr>(s: Option<&str>) -> Result<T, String> {
+    let s = s.ok_or("Missing argument")?;
+    s.parse().map_err(|_| format!("Bad number: {}", s))
+}
+
+fn format_outputs(outputs: &[Int]) -> String {
+    let ascii = outputs.iter().all(|&v| v == 10 || (32..127).contains(&v));
+    if ascii && !outputs.is_empty() {
+        outputs.iter().map(|&v| v as u8 as char).collect()
+    } else {
+        outputs.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+    }
+}
+
+impl Debugger {
+    // Report an event, returning true if execution should stop.
+    fn handle_event(&mut self, event: Event) -> bool {
+        match event {
+            Event::Output(val) => {
+                self.outputs.push(val);
+                return false;
+            }
+            Event::InputNeeded => println!("Input needed"),
+            Event::Halted => println!("Halted"),
+            Event::Breakpoint(addr) => println!("Breakpoint at {}", addr),
+            Event::Watch { pc, addr, access } => {
+                println!("Watchpoint: {:?} of [{}] by instruction at {}, now {}",
+                         access, addr, pc, self.machine.get_u(addr).unwrap_or(0));
+            }
+        }
+        true
+    }
+
+    fn show_pc(&self) {
+        print!("{}", self.disassemble(self.machine.pc(), 1));
+    }
+
+    fn show_pending(&self) {
+        if !self.outputs.is_empty() {
+            println!("({} outputs pending)", self.outputs.len());
+        }
+    }
+
+    // Disassemble count instructions from start, marking the pc.
+    fn disassemble(&self, start: usize, count: usize) -> String {
+        let pc = self.machine.pc();
+        let marker = format!("; {:05}:", pc);
+        self.machine.disassemble(start, count)
+            .lines()
+            .map(|l| format!("{} {}\n", if l.contains(&marker) { "=>" } else { "  " }, l))
+            .collect()
+    }
+
+    // Find an instruction boundary a few instructions before the pc by
+    // sweeping from the start of memory, falling back to the pc itself.
+    fn start_before_pc(&self, before: usize) -> usize {
+        let pc = self.machine.pc();
+        let mem = self.machine.memory();
+        let mut starts = Vec::new();
+        let mut addr = 0;
+        while addr < pc {
+            starts.push(addr);
+            addr += decode_line(mem, addr).size();
+        }
+        if addr == pc && !starts.is_empty() {
+            starts[starts.len().saturating_sub(before)]
+        } else {
+            pc
+        }
+    }
+
+    fn attach_tracers(&mut self) {
+        self.machine.clear_tracers();
+        if let Some(profile) = &self.profile {
+            self.machine.add_tracer(profile.clone());
+        }
+        if let Some(coverage) = &self.coverage {
+            self.machine.add_tracer(coverage.clone());
+        }
+        if let Some(trace) = &self.trace {
+            self.machine.add_tracer(trace.clone());
+        }
+    }
+
+    fn command(&mut self, line: &str) -> Result<bool, String> {
+        let mut words = line.split_whitespace();
+        let cmd = match words.next() {
+            Some(cmd) => cmd,
+            None => return Ok(true),
+        };
+        let machine = &mut self.machine;
+        match cmd {
+            "s" | "step" => {
+                let count: usize = words.next().map(|s| parse_num(Some(s))).transpose()?.unwrap_or(1);
+                for _ in 0..count {
+                    if let Some(event) = self.machine.step_event().map_err(|e| e.to_string())? {
+                        if self.handle_event(event) {
+                            break;
+                        }
+                    }
+                }
+                self.show_pc();
+                self.show_pending();
+            }
+            "c" | "continue" => {
+                loop {
+                    let event = self.machine.run_until_event().map_err(|e| e.to_string())?;
+                    if self.handle_event(event) {
+                        break;
+                    }
+                }
+                self.show_pc();
+                self.show_pending();
+            }
+            "bs" | "back" => {
+                let count: usize = words.next().map(|s| parse_num(Some(s))).transpose()?.unwrap_or(1);
+                for _ in 0..count {
+                    if !machine.step_back() {
+                        println!("No more history");
+                        break;
+                    }
+                }
+                self.show_pc();
+            }
+            "rewind" => {
+                let step = parse_num(words.next())?;
+                if !machine.rewind_to(step) {
+                    return Err(match machine.earliest_step() {
+                        Some(earliest) => format!("Can only rewind to steps {} to {}", earliest, machine.steps()),
+                        None => "Not recording (see record)".into(),
+                    });
+                }
+                self.show_pc();
+            }
+            "record" => match words.next() {
+                Some("off") => machine.disable_history(),
+                limit => machine.enable_history(limit.map(|s| parse_num(Some(s))).transpose()?),
+            },
+            "b" | "break" => machine.add_breakpoint(parse_num(words.next())?),
+            "d" | "delete" => {
+                let addr = parse_num(words.next())?;
+                if !machine.remove_breakpoint(addr) && !machine.remove_watchpoint(addr) {
+                    return Err(format!("Nothing set at {}", addr));
+                }
+            }
+            "w" | "watch" => {
+                let addr = parse_num(words.next())?;
+                let watch = match words.next() {
+                    Some("r") => Watch::Read,
+                    Some("w") => Watch::Write,
+                    Some("rw") | None => Watch::ReadWrite,
+                    Some(other) => return Err(format!("Bad watch kind {}", other)),
+                };
+                machine.add_watchpoint(addr, watch);
+            }
+            "l" | "list" => {
+                let mut breaks: Vec<_> = machine.breakpoints().collect();
+                breaks.sort_unstable();
+                for addr in breaks {
+                    println!("break {}", addr);
+                }
+                let mut watches: Vec<_> = machine.watchpoints().collect();
+                watches.sort_unstable_by_key(|&(addr, _)| addr);
+                for (addr, watch) in watches {
+                    println!("watch {} {:?}", addr, watch);
+                }
+            }
+            "x" => {
+                let addr: usize = parse_num(words.next())?;
+                let count: usize = words.next().map(|s| parse_num(Some(s))).transpose()?.unwrap_or(8);
+                let end = addr.saturating_add(count);
+                for row in (addr..end).step_by(8) {
+                    let vals = (row..row.saturating_add(8).min(end))
+                                   .map(|a| machine.get_u(a).unwrap_or(0).to_string())
+                                   .collect::<Vec<_>>();
+                    println!("{:05}: {}", row, vals.join(" "));
+                }
+            }
+            "set" => {
+                let addr: Int = parse_num(words.next())?;
+                let val: Int = parse_num(words.next())?;
+                machine.set(addr, val).map_err(|e| e.to_string())?;
+            }
+            "r" | "regs" => {
+                println!("pc={} rel_base={} step={}{}", machine.pc(), machine.rel_base(),
+                         machine.steps(), if machine.is_halted() { " (halted)" } else { "" });
+                println!("inputs: {:?}", machine.get_inputs());
+                self.show_pc();
+            }
+            "pc" => machine.set_pc(parse_num(words.next())?),
+            "rb" => machine.set_rel_base(parse_num(words.next())?),
+            "in" => {
+                for word in words {
+                    machine.send_input(parse_num(Some(word))?);
+                }
+            }
+            "ins" => {
+                let text = line.trim_start()[cmd.len()..].trim_start();
+                machine.send_input_ascii(text);
+                machine.send_input(10);
+            }
+            "out" => {
+                println!("{}", format_outputs(&self.outputs));
+                self.outputs.clear();
+            }
+            "dis" => {
+                let count = 10;
+                let text = match words.next() {
+                    Some(s) => {
+                        let addr = parse_num(Some(s))?;
+                        let count = words.next().map(|s| parse_num(Some(s))).transpose()?.unwrap_or(count);
+                        self.disassemble(addr, count)
+                    }
+                    None => self.disassemble(self.start_before_pc(count / 2), count),
+                };
+                print!("{}", text);
+            }
+            "trace" => {
+                self.trace = match words.next() {
+                    Some("off") => None,
+                    Some(filename) => {
+                        let file = std::fs::File::create(filename).map_err(|e| e.to_string())?;
+                        let writer = TraceWriter::new(io::BufWriter::new(file));
+                        Some(Arc::new(Mutex::new(writer)))
+                    }
+                    None => return Err("Missing argument".into()),
+                };
+                self.attach_tracers();
+            }
+            "profile" => match words.next() {
+                Some("on") => {
+                    self.profile = Some(Arc::new(Mutex::new(Profile::new())));
+                    self.attach_tracers();
+                }
+                Some("off") => {
+                    self.profile = None;
+                    self.attach_tracers();
+                }
+                arg => {
+                    let count = arg.map(|s| parse_num(Some(s))).transpose()?.unwrap_or(10);
+                    let profile = self.profile.as_ref().ok_or("Not profiling (try profile on)")?;
+                    print!("{}", profile.lock().unwrap().report(machine.memory(), count));
+                }
+            }
+            "coverage" => match words.next() {
+                Some("on") => {
+                    self.coverage = Some(Arc::new(Mutex::new(Coverage::new())));
+                    self.attach_tracers();
+                }
+                Some("off") => {
+                    self.coverage = None;
+                    self.attach_tracers();
+                }
+                arg => {
+                    let coverage = self.coverage.as_ref().ok_or("Not recording coverage (try coverage on)")?;
+                    let text = coverage.lock().unwrap().report(&self.program, 0);
+                    match arg {
+                        Some(filename) => std::fs::write(filename, text).map_err(|e| e.to_string())?,
+                        None => print!("{}", text),
+                    }
+                }
+            }
+            "cfg" => {
+                let analysis = machine.analyse();
+                let filename = words.next().ok_or("Missing argument")?;
+                std::fs::write(filename, analysis.to_dot()).map_err(|e| e.to_string())?;
+                println!("{} blocks, {} indirect jumps, {} self-modifying writes",
+                         analysis.blocks.len(), analysis.indirect_jumps.len(),
+                         analysis.self_modifying.len());
+                for (start, end) in &analysis.unreachable {
+                    println!("Unreachable: {}-{}", start, end - 1);
+                }
+            }
+            "decompile" => {
+                let text = decompile(machine.memory(), &[machine.pc()]).pseudocode();
+                match words.next() {
+                    Some(filename) => std::fs::write(filename, text).map_err(|e| e.to_string())?,
+                    None => print!("{}", text),
+                }
+            }
+            "help" | "h" | "?" => println!("{}", HELP),
+            "q" | "quit" => return Ok(false),
+            _ => return Err(format!("Unknown command {} (try help)", cmd)),
+        }
+        Ok(true)
+    }
+}
+
+fn load_program(filename: &str) -> Result<Vec<Int>, String> {
+    if filename.ends_with(".asm") {
+        let text = std::fs::read_to_string(filename).map_err(|e| e.to_string())?;
+        assemble(&text).map_err(|e| e.to_string())
+    } else {
+        load_file(filename).map_err(|e| e.to_string())
+    }
+}
+
+fn main() {
+    let filename = match std::env::args().nth(1) {
+        Some(f) => f,
+        None => {
+            eprintln!("Usage: intcode_debug <program.txt|program.asm>");
+            std::process::exit(1);
+        }
+    };
+    let program = match load_program(&filename) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("Error loading {}: {}", filename, e);
+            std::process::exit(1);
+        }
+    };
+    let mut debugger = Debugger {
+        machine: IntcodeMachine::new(&program),
+        program: program.clone(),
+        outputs: Vec::new(),
+        profile: None,
+        coverage: None,
+        trace: None,
+    };
+    let mut editor = LineEditor::new();
+    println!("Loaded {} words.  Type help for commands.", program.len());
+    debugger.show_pc();
+    let mut last = String::new();
+    while let Some(mut line) = editor.read_line("(icdb) ") {
+        if line.is_empty() {
+            line = last.clone();
+        } else if line == "history" {
+            for (i, entry) in editor.history.iter().enumerate() {
+                println!("{:4}  {}", i, entry);
+            }
+            continue;
+        } else if let Some(n) = line.strip_prefix('!') {
+            match n.parse::<usize>().ok().and_then(|n| editor.history.get(n)) {
+                Some(entry) => {
+                    line = entry.clone();
+                    println!("{}", line);
+                    // Record what was actually run rather than the !n.
+                    editor.history.pop();
+                    editor.history.push(line.clone());
+                }
+                None => {
+                    println!("No such history entry");
+                    continue;
+                }
+            }
+        }
+        match debugger.command(&line) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => println!("Error: {}", e),
+        }
+        last = line;
+    }
+}
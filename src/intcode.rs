@@ -1,157 +1,450 @@
 use std::convert::TryFrom;
 pub type Int = isize;
-use std::io::{Write};
+use std::io::{BufRead,Write};
+use std::collections::{HashMap,HashSet,VecDeque};
 use std::fmt::{Debug,Display};
-
-#[derive(Debug)]
-pub enum Error {
-    UnknownInstruction,
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool,Ordering};
+
+pub mod asm;
+pub mod disasm;
+pub mod snapshot;
+pub mod network;
+pub mod io;
+pub mod amplifier;
+pub mod memory;
+pub mod trace;
+pub mod cache;
+pub mod word;
+pub mod script;
+pub mod history;
+pub mod analysis;
+pub mod custom;
+pub mod device;
+pub mod search;
+pub mod load;
+pub mod decompile;
+pub mod compile;
+pub mod fuzz;
+pub mod coverage;
+
+pub use self::io::{Input,Output};
+pub use self::memory::Memory;
+pub use self::word::Word;
+
+// Faults during execution record the pc and the raw instruction there.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Error<W = Int> {
+    UnknownInstruction { pc: usize, insn: W },
     IndexOutOfBounds,
     NegativeIndex,
-    InvalidMode,
+    InvalidMode { pc: usize, insn: W },
     Unknown,
     InputNeeded,
+    NoOutput,
+    Overflow { pc: usize, insn: W },
+    NegativeJump { pc: usize, insn: W, target: W },
+    InvalidWriteMode { pc: usize, insn: W },
+    NegativeAddress { pc: usize, insn: W, addr: W },
+    // An address or jump target too big for a usize (wide words only)
+    AddressTooLarge { pc: usize, insn: W, addr: W },
+    // The instruction at pc wasn't run because of the step limit or
+    // the cancel flag.
+    StepLimit { pc: usize },
+    Cancelled { pc: usize },
+    // The device at addr panicked earlier, poisoning its lock.
+    DevicePoisoned { pc: usize, addr: usize },
 }
 
-impl std::error::Error for Error {
+impl<W: Debug> std::error::Error for Error<W> {
 }
 
-impl Display for Error {
+impl<W: Debug> Display for Error<W> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
         <Self as Debug>::fmt(self, f)
     }
 }
 
-#[derive(Debug)]
-pub enum Event {
-    Output(Int),
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum Mode {
+    Position,
+    Immediate,
+    Relative,
+}
+
+impl Mode {
+    pub fn from_digit(digit: Int) -> Option<Mode> {
+        match digit {
+            0 => Some(Mode::Position),
+            1 => Some(Mode::Immediate),
+            2 => Some(Mode::Relative),
+            _ => None,
+        }
+    }
+
+    pub fn digit(self) -> Int {
+        match self {
+            Mode::Position => 0,
+            Mode::Immediate => 1,
+            Mode::Relative => 2,
+        }
+    }
+}
+
+#[derive(Debug,PartialEq,Eq)]
+pub struct OpInfo {
+    pub opcode: Int,
+    pub mnemonic: &'static str,
+    pub params: usize,
+    // Whether the last parameter is an address written to
+    pub writes: bool,
+}
+
+pub const OPCODES: [OpInfo; 10] = [
+    OpInfo { opcode: 1, mnemonic: "add", params: 3, writes: true },
+    OpInfo { opcode: 2, mnemonic: "mul", params: 3, writes: true },
+    OpInfo { opcode: 3, mnemonic: "in", params: 1, writes: true },
+    OpInfo { opcode: 4, mnemonic: "out", params: 1, writes: false },
+    OpInfo { opcode: 5, mnemonic: "jt", params: 2, writes: false },
+    OpInfo { opcode: 6, mnemonic: "jf", params: 2, writes: false },
+    OpInfo { opcode: 7, mnemonic: "lt", params: 3, writes: true },
+    OpInfo { opcode: 8, mnemonic: "eq", params: 3, writes: true },
+    OpInfo { opcode: 9, mnemonic: "arb", params: 1, writes: false },
+    OpInfo { opcode: 99, mnemonic: "hlt", params: 0, writes: false },
+];
+
+pub fn op_info(opcode: Int) -> Option<&'static OpInfo> {
+    OPCODES.iter().find(|op| op.opcode == opcode)
+}
+
+pub fn op_by_mnemonic(mnemonic: &str) -> Option<&'static OpInfo> {
+    OPCODES.iter().find(|op| op.mnemonic == mnemonic)
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Instruction {
+    pub op: &'static OpInfo,
+    pub modes: [Mode; 3],
+}
+
+impl Instruction {
+    pub fn size(&self) -> usize {
+        1 + self.op.params
+    }
+}
+
+// Decode an instruction word, returning None unless it is exactly what
+// the assembler would produce: a known opcode, valid modes for each of
+// its parameters (never immediate for a write) and no stray mode digits.
+pub fn decode(insn: Int) -> Option<Instruction> {
+    if insn < 0 {
+        return None;
+    }
+    let op = op_info(insn % 100)?;
+    let mut modes = [Mode::Position; 3];
+    let mut val = insn / 100;
+    for mode in modes.iter_mut().take(op.params) {
+        *mode = Mode::from_digit(val % 10)?;
+        val /= 10;
+    }
+    if val != 0 {
+        return None;
+    }
+    if op.writes && modes[op.params - 1] == Mode::Immediate {
+        return None;
+    }
+    Some(Instruction { op, modes })
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Watch {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Watch {
+    fn matches(self, access: Access) -> bool {
+        !matches!((self, access), (Watch::Read, Access::Write) | (Watch::Write, Access::Read))
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Event<W = Int> {
+    Output(W),
     InputNeeded,
     Halted,
+    // About to execute the instruction at this address
+    Breakpoint(usize),
+    // The instruction at `pc` accessed a watched address
+    Watch { pc: usize, addr: usize, access: Access },
 }
 
-pub struct IntcodeMachine {
-    data: Vec<Int>,
+// The word type W defaults to Int; see WideMachine for others.
+#[derive(Clone)]
+pub struct IntcodeMachine<I = VecDeque<Int>, O = VecDeque<Int>, W = Int> {
+    data: Memory<W>,
     pc: usize,
-    rel_base: Int,
+    rel_base: W,
     halted: bool,
-    inputs: Vec<Int>,
-    outputs: Vec<Int>,
+    inputs: I,
+    outputs: O,
+    breakpoints: HashSet<usize>,
+    // Set after stopping at a breakpoint, so that resuming doesn't stop again.
+    break_skip: Option<usize>,
+    watchpoints: HashMap<usize, Watch>,
+    watch_hit: Option<Event<W>>,
+    tracers: Vec<trace::SharedTracer<W>>,
+    cache: Option<cache::DecodeCache<W>>,
+    steps: u64,
+    step_limit: Option<u64>,
+    // Shared with clones, so one flag can stop a whole search.
+    cancel: Option<Arc<AtomicBool>>,
+    history: Option<history::History<W>>,
+    custom_ops: HashMap<Int, custom::CustomOp<I, O, W>>,
+    devices: Vec<(std::ops::Range<usize>, device::SharedDevice<W>)>,
+    // Only while a traced instruction runs
+    trace_log: Option<trace::TraceLog<W>>,
 }
 
 impl IntcodeMachine {
     pub fn new(data: &[Int]) -> IntcodeMachine {
+        IntcodeMachine::with_io(data, VecDeque::new(), VecDeque::new())
+    }
+
+    pub fn from_memory(memory: Memory) -> IntcodeMachine {
+        IntcodeMachine::with_io(memory, VecDeque::new(), VecDeque::new())
+    }
+}
+
+pub type WideMachine<W> = IntcodeMachine<VecDeque<W>, VecDeque<W>, W>;
+
+impl<W: Word> WideMachine<W> {
+    // Load an ordinary program into a machine with wider words.
+    pub fn wide(data: &[Int]) -> WideMachine<W> {
+        WideMachine::with_words(&word::widen(data))
+    }
+
+    pub fn with_words(data: &[W]) -> WideMachine<W> {
+        IntcodeMachine::with_io(data, VecDeque::new(), VecDeque::new())
+    }
+}
+
+impl<I: Input<W>, O: Output<W>, W: Word> IntcodeMachine<I, O, W> {
+    pub fn with_io(data: impl Into<Memory<W>>, inputs: I, outputs: O) -> IntcodeMachine<I, O, W> {
         IntcodeMachine {
-            data: Vec::from(data),
+            data: data.into(),
             pc: 0,
-            rel_base: 0,
+            rel_base: W::default(),
             halted: false,
-            inputs: Vec::new(),
-            outputs: Vec::new(),
+            inputs,
+            outputs,
+            breakpoints: HashSet::new(),
+            break_skip: None,
+            watchpoints: HashMap::new(),
+            watch_hit: None,
+            tracers: Vec::new(),
+            cache: None,
+            steps: 0,
+            step_limit: None,
+            cancel: None,
+            history: None,
+            custom_ops: HashMap::new(),
+            devices: Vec::new(),
+            trace_log: None,
+        }
+    }
+
+    pub fn step(&mut self) -> Result<(), Error<W>> {
+        if self.step_limit.is_some() || self.cancel.is_some() {
+            self.check_limits()?;
+        }
+        if self.watchpoints.is_empty() && self.tracers.is_empty() && self.history.is_none() {
+            self.execute()?;
+        } else {
+            self.step_hooked()?;
+        }
+        self.steps += 1;
+        Ok(())
+    }
+
+    fn check_limits(&self) -> Result<(), Error<W>> {
+        if self.step_limit.is_some_and(|limit| self.steps >= limit) {
+            return Err(Error::StepLimit { pc: self.pc });
+        }
+        if self.cancel.as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed)) {
+            return Err(Error::Cancelled { pc: self.pc });
         }
+        Ok(())
     }
 
-    pub fn step(&mut self) -> Result<(), Error> {
-        let insn = self.data[self.pc];
-        let opcode = insn % 100;
-        let (mode0, mode1, mode2) = {
-            let mut val = insn / 100;
-            let mode0 = val % 10;
-            val /= 10;
-            let mode1 = val % 10;
-            val /= 10;
-            let mode2 = val % 10;
+    // Step with watchpoints, tracers and/or history to update.
+    #[inline(never)]
+    fn step_hooked(&mut self) -> Result<(), Error<W>> {
+        let pc = self.pc;
+        let accesses = self.operand_accesses();
+        let traced = if self.tracers.is_empty() { None } else { Some(self.begin_trace()) };
+        if let Some(history) = &mut self.history {
+            history.begin(pc, self.rel_base.clone(), self.halted);
+        }
+        let result = self.execute();
+        if let Some(history) = &mut self.history {
+            if result.is_ok() { history.commit() } else { history.abort() }
+        }
+        if let Err(e) = result {
+            self.trace_log = None;
+            return Err(e);
+        }
+        if let Some(line) = traced {
+            self.end_trace(pc, line);
+        }
+        self.watch_hit = accesses.into_iter()
+            .find(|(addr, access)| {
+                self.watchpoints.get(addr).is_some_and(|w| w.matches(*access))
+            })
+            .map(|(addr, access)| Event::Watch { pc, addr, access });
+        Ok(())
+    }
 
-            (mode0, mode1, mode2)
+    // The data addresses the instruction at pc will read or write.
+    fn operand_accesses(&self) -> Vec<(usize, Access)> {
+        let mut result = Vec::new();
+        let insn = match self.data.get(self.pc).to_int().and_then(decode) {
+            Some(insn) => insn,
+            None => return result,
         };
-        // Speculatively get arguments
-        let arg0 = self.get_u(self.pc + 1)?;
-        let val0 = self.get_arg(arg0, mode0)?;
-        let arg1 = self.get_u(self.pc + 2)?;
-        let val1 = self.get_arg(arg1, mode1)?;
-        match opcode {
-            1 => {
-                let addr = self.get_arg_addr(self.get_u(self.pc + 3)?, mode2);
-                self.set(addr, val0.checked_add(val1).unwrap());
-                self.pc += 4;
+        for i in 0..insn.op.params {
+            // The instruction itself faults if its arguments run off the end.
+            let arg = match self.pc.checked_add(1 + i) {
+                Some(addr) => self.data.get(addr),
+                None => break,
+            };
+            let addr = match insn.modes[i] {
+                Mode::Position => Some(arg),
+                Mode::Immediate => continue,
+                Mode::Relative => arg.checked_add(&self.rel_base),
+            };
+            let access = if insn.op.writes && i == insn.op.params - 1 {
+                Access::Write
+            } else {
+                Access::Read
+            };
+            if let Some(addr) = addr.and_then(|a| a.to_usize()) {
+                result.push((addr, access));
             }
-            2 => {
-                let addr = self.get_arg_addr(self.get_u(self.pc + 3)?, mode2);
-                self.set(addr, val0.checked_mul(val1).unwrap());
-                self.pc += 4;
+        }
+        result
+    }
+
+    fn execute(&mut self) -> Result<(), Error<W>> {
+        if self.cache.is_some() {
+            if let Some(result) = self.execute_cached() {
+                return result;
+            }
+        }
+        self.interpret()
+    }
+
+    fn interpret(&mut self) -> Result<(), Error<W>> {
+        let pc = self.pc;
+        let insn = self.data.get(pc);
+        let code = match insn.to_int() {
+            Some(code) => code,
+            None => return Err(Error::UnknownInstruction { pc, insn }),
+        };
+        let opcode = code % 100;
+        let modes = [(code / 100) % 10, (code / 1000) % 10, (code / 10000) % 10];
+        match opcode {
+            1 | 2 | 7 | 8 => {
+                let next = self.pc_offset(4, &insn)?;
+                let val0 = self.read_param(0, modes[0], &insn)?;
+                let val1 = self.read_param(1, modes[1], &insn)?;
+                let result = match opcode {
+                    1 => val0.checked_add(&val1).ok_or_else(|| Error::Overflow { pc, insn: insn.clone() })?,
+                    2 => val0.checked_mul(&val1).ok_or_else(|| Error::Overflow { pc, insn: insn.clone() })?,
+                    7 => W::from_int(if val0 < val1 { 1 } else { 0 }),  // less than
+                    _ => W::from_int(if val0 == val1 { 1 } else { 0 }),  // equals
+                };
+                let addr = self.param_addr(2, modes[2], &insn)?;
+                self.store(addr, result)?;
+                self.pc = next;
             }
             3 => {
+                let next = self.pc_offset(2, &insn)?;
+                let addr = self.param_addr(0, modes[0], &insn)?;
                 let val = self.get_input()?;
-                let addr = self.get_arg_addr(self.get_u(self.pc + 1)?, mode0);
-                self.set(addr, val);
-                self.pc += 2;
+                self.store(addr, val)?;
+                self.pc = next;
             }
             4 => {
+                let next = self.pc_offset(2, &insn)?;
+                let val0 = self.read_param(0, modes[0], &insn)?;
                 self.output(val0);
-                self.pc += 2;
+                self.pc = next;
             }
-            5 => {  // Jump if true
-                if val0 != 0 {
-                    self.pc = usize::try_from(val1).unwrap();
+            5 | 6 => {  // Jump if true/false
+                let val0 = self.read_param(0, modes[0], &insn)?;
+                let val1 = self.read_param(1, modes[1], &insn)?;
+                if val0.is_zero() != (opcode == 5) {
+                    self.pc = jump_target(pc, &insn, val1)?;
                 } else {
-                    self.pc += 3;
+                    self.pc = self.pc_offset(3, &insn)?;
                 }
             }
-            6 => {  // Jump if false
-                if val0 == 0 {
-                    self.pc = usize::try_from(val1).unwrap();
-                } else {
-                    self.pc += 3;
-                }
-            }
-            7 => { // less than
-                let addr = self.get_arg_addr(self.get_u(self.pc + 3)?, mode2);
-                self.set(addr, if val0 < val1 { 1 } else { 0 });
-                self.pc += 4;
-            }
-            8 => { // equals
-                let addr = self.get_arg_addr(self.get_u(self.pc + 3)?, mode2);
-                self.set(addr, if val0 == val1 { 1 } else { 0 });
-                self.pc += 4;
-            }
             9 => { // Adjust relative base
-                self.rel_base += val0;
-                self.pc += 2;
+                let next = self.pc_offset(2, &insn)?;
+                let val0 = self.read_param(0, modes[0], &insn)?;
+                self.rel_base = self.rel_base.checked_add(&val0)
+                                    .ok_or(Error::Overflow { pc, insn })?;
+                self.pc = next;
             }
             99 => {
                 self.halted = true;
             }
             _ => {
-                return Err(Error::UnknownInstruction);
-                //panic!("Unknown instruction {} at {}", insn, self.pc);
+                return self.execute_custom(code, insn);
             }
         }
         Ok(())
     }
 
-    pub fn run_until_halt(&mut self) -> Result<(), Error> {
+    pub fn run_until_halt(&mut self) -> Result<(), Error<W>> {
         while !self.halted {
             self.step()?;
         }
         Ok(())
     }
 
-    pub fn run_until_output(&mut self) -> Result<Option<Int>, Error> {
+    pub fn run_until_output(&mut self) -> Result<Option<W>, Error<W>> {
         while !self.halted {
-            if self.outputs.len() > 0 {
-                return Ok(Some(self.outputs.remove(0)));
+            if let Some(val) = self.outputs.take() {
+                return Ok(Some(val));
             }
             self.step()?;
         }
         Ok(None)
     }
 
-    pub fn run_until_event(&mut self) -> Result<Event, Error> {
+    pub fn run_until_event(&mut self) -> Result<Event<W>, Error<W>> {
         while !self.halted {
-            if self.outputs.len() > 0 {
-                return Ok(Event::Output(self.outputs.remove(0)));
+            if let Some(val) = self.outputs.take() {
+                return Ok(Event::Output(val));
+            }
+            if !self.breakpoints.is_empty() && self.breakpoints.contains(&self.pc)
+                && self.break_skip != Some(self.pc) {
+                self.break_skip = Some(self.pc);
+                return Ok(Event::Breakpoint(self.pc));
             }
+            // The breakpoint is only re-armed once the instruction there
+            // has run, so stopping for input doesn't hit it again.
             match self.step() {
-                Ok(_) => {}
+                Ok(_) => self.break_skip = None,
                 Err(Error::InputNeeded) => {
                     return Ok(Event::InputNeeded);
                 }
@@ -159,10 +452,257 @@ impl IntcodeMachine {
                     return Err(error);
                 }
             }
+            if let Some(event) = self.watch_hit.take() {
+                return Ok(event);
+            }
         }
         Ok(Event::Halted)
     }
 
+    // Execute a single instruction, ignoring breakpoints, and report any
+    // event which run_until_event would have stopped for.
+    pub fn step_event(&mut self) -> Result<Option<Event<W>>, Error<W>> {
+        if self.halted {
+            return Ok(Some(Event::Halted));
+        }
+        match self.step() {
+            Ok(_) => {}
+            Err(Error::InputNeeded) => {
+                return Ok(Some(Event::InputNeeded));
+            }
+            Err(error) => {
+                return Err(error);
+            }
+        }
+        if let Some(event) = self.watch_hit.take() {
+            return Ok(Some(event));
+        }
+        if let Some(val) = self.outputs.take() {
+            return Ok(Some(Event::Output(val)));
+        }
+        if self.halted {
+            return Ok(Some(Event::Halted));
+        }
+        Ok(None)
+    }
+
+    // Instructions executed so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // Stop with Error::StepLimit once `steps()` reaches the limit.  The
+    // machine can carry on after the limit is raised or removed.
+    pub fn set_step_limit(&mut self, limit: Option<u64>) {
+        self.step_limit = limit;
+    }
+
+    // Allow `budget` more steps from now.
+    pub fn set_step_budget(&mut self, budget: u64) {
+        self.step_limit = Some(self.steps.saturating_add(budget));
+    }
+
+    // Stop with Error::Cancelled once the flag is set.
+    pub fn set_cancel_flag(&mut self, flag: Option<Arc<AtomicBool>>) {
+        self.cancel = flag;
+    }
+
+    pub fn add_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item=usize> + '_ {
+        self.breakpoints.iter().cloned()
+    }
+
+    pub fn add_watchpoint(&mut self, addr: usize, watch: Watch) {
+        self.watchpoints.insert(addr, watch);
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: usize) -> bool {
+        self.watchpoints.remove(&addr).is_some()
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item=(usize, Watch)> + '_ {
+        self.watchpoints.iter().map(|(&addr, &watch)| (addr, watch))
+    }
+
+    pub fn get(&self, idx: Int) -> Result<W, Error<W>> {
+        let idx = usize::try_from(idx).map_err(|_| Error::NegativeIndex)?;
+        self.get_u(idx)
+    }
+
+    pub fn get_u(&self, idx: usize) -> Result<W, Error<W>> {
+        self.load(idx)
+    }
+
+    // The address `offset` words on from pc.  Only wide words can jump
+    // far enough for there to be none, and they can hold the address.
+    fn pc_offset(&self, offset: usize, insn: &W) -> Result<usize, Error<W>> {
+        let pc = self.pc;
+        pc.checked_add(offset).ok_or_else(|| {
+            let half = W::from_int((pc / 2) as Int);
+            let addr = half.checked_add(&half)
+                           .and_then(|a| a.checked_add(&W::from_int((pc % 2 + offset) as Int)));
+            Error::AddressTooLarge { pc, insn: insn.clone(), addr: addr.unwrap_or_else(|| insn.clone()) }
+        })
+    }
+
+    // The address referred to by parameter n of the current instruction.
+    fn param_addr(&self, n: usize, mode: Int, insn: &W) -> Result<usize, Error<W>> {
+        let pc = self.pc;
+        let arg = self.data.get(self.pc_offset(1 + n, insn)?);
+        let addr = match mode {
+            0 => arg,
+            2 => arg.checked_add(&self.rel_base).ok_or_else(|| Error::Overflow { pc, insn: insn.clone() })?,
+            1 => return Err(Error::InvalidWriteMode { pc, insn: insn.clone() }),
+            _ => return Err(Error::InvalidMode { pc, insn: insn.clone() }),
+        };
+        to_address(pc, insn, addr)
+    }
+
+    fn read_param(&self, n: usize, mode: Int, insn: &W) -> Result<W, Error<W>> {
+        let val = if mode == 1 {
+            self.data.get(self.pc_offset(1 + n, insn)?)
+        } else {
+            self.load(self.param_addr(n, mode, insn)?)?
+        };
+        self.note_read(&val);
+        Ok(val)
+    }
+
+    fn get_input(&mut self) -> Result<W, Error<W>> {
+        let val = self.inputs.read().ok_or(Error::InputNeeded)?;
+        if let Some(history) = &mut self.history {
+            history.note_input(val.clone());
+        }
+        Ok(val)
+    }
+
+    fn output(&mut self, val: W) {
+        if let Some(history) = &mut self.history {
+            history.note_output();
+        }
+        self.outputs.write(val);
+    }
+
+    pub fn set(&mut self, idx: Int, val: W) -> Result<(), Error<W>> {
+        let idx = usize::try_from(idx).map_err(|_| Error::NegativeIndex)?;
+        self.store(idx, val)
+    }
+
+    fn store(&mut self, addr: usize, val: W) -> Result<(), Error<W>> {
+        if let Some(log) = &mut self.trace_log {
+            log.writes.push((addr, val.clone()));
+        }
+        if !self.devices.is_empty() {
+            if let Some((offset, device)) = self.device_at(addr) {
+                let mut device = device.lock().map_err(|_| Error::DevicePoisoned { pc: self.pc, addr })?;
+                device.write(offset, val);
+                return Ok(());
+            }
+        }
+        if let Some(history) = &mut self.history {
+            history.note_write(addr, self.data.get(addr));
+        }
+        self.data.set(addr, val);
+        if let Some(cache) = &mut self.cache {
+            cache.invalidate(addr);
+        }
+        Ok(())
+    }
+
+    pub fn get_data(&self, start: usize, len: usize) -> Vec<W> {
+        self.data.read(start, len)
+    }
+
+    pub fn memory(&self) -> &Memory<W> {
+        &self.data
+    }
+
+    pub fn memory_len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    // Moving the pc also resumes a halted machine.
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+        self.halted = false;
+    }
+
+    pub fn rel_base(&self) -> W {
+        self.rel_base.clone()
+    }
+
+    pub fn set_rel_base(&mut self, rel_base: W) {
+        self.rel_base = rel_base;
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn input_mut(&mut self) -> &mut I {
+        &mut self.inputs
+    }
+
+    pub fn output_mut(&mut self) -> &mut O {
+        &mut self.outputs
+    }
+}
+
+fn to_address<W: Word>(pc: usize, insn: &W, addr: W) -> Result<usize, Error<W>> {
+    match addr.to_usize() {
+        Some(addr) => Ok(addr),
+        None if addr.is_negative() => Err(Error::NegativeAddress { pc, insn: insn.clone(), addr }),
+        None => Err(Error::AddressTooLarge { pc, insn: insn.clone(), addr }),
+    }
+}
+
+fn jump_target<W: Word>(pc: usize, insn: &W, target: W) -> Result<usize, Error<W>> {
+    match target.to_usize() {
+        Some(target) => Ok(target),
+        None if target.is_negative() => Err(Error::NegativeJump { pc, insn: insn.clone(), target }),
+        None => Err(Error::AddressTooLarge { pc, insn: insn.clone(), addr: target }),
+    }
+}
+
+impl<W: Word, O: Output<W>> IntcodeMachine<VecDeque<W>, O, W> {
+    pub fn send_input(&mut self, val: W) {
+        self.inputs.push_back(val);
+    }
+
+    pub fn send_input_ascii(&mut self, input: &str) {
+        for c in input.chars() {
+            self.send_input(W::from_int(c as Int));
+        }
+    }
+
+    pub fn get_inputs(&mut self) -> &[W] {
+        self.inputs.make_contiguous()
+    }
+}
+
+impl<W: Word, I: Input<W>> IntcodeMachine<I, VecDeque<W>, W> {
+    // The outputs not yet taken, oldest first.
+    pub fn get_outputs(&self) -> Vec<W> {
+        self.outputs.iter().cloned().collect()
+    }
+
+    pub fn take_outputs(&mut self) -> Vec<W> {
+        self.outputs.drain(..).collect()
+    }
+}
+
+impl IntcodeMachine {
     pub fn run_ascii(&mut self, input: &str) -> Result<String, Error> {
         self.send_input_ascii(input);
         while !self.halted {
@@ -181,18 +721,27 @@ impl IntcodeMachine {
     }
 
     pub fn run_ascii_interactive(&mut self) -> Result<Event, Error> {
+        self.run_ascii_io(std::io::stdin().lock(), std::io::stdout())
+    }
+
+    // Run with a line of input from `input` whenever needed, writing
+    // output as text.  Stops early (with InputNeeded) if input runs out.
+    pub fn run_ascii_io<R: BufRead, W: Write>(&mut self, mut input: R, mut output: W) -> Result<Event, Error> {
         loop {
             match self.run_until_event()? {
                 Event::Output(c) => {
-                    print!("{}", c as u8 as char);
-                    std::io::stdout().flush().unwrap();
+                    let _ = output.write_all(&[c as u8]);
+                    let _ = output.flush();
                 }
                 Event::InputNeeded => {
                     let mut s = String::new();
-                    let _count = std::io::stdin().read_line(&mut s);
-                    self.send_input_ascii(&s);
+                    match input.read_line(&mut s) {
+                        Ok(0) | Err(_) => { return Ok(Event::InputNeeded); }
+                        Ok(_) => { self.send_input_ascii(&s); }
+                    }
                 }
                 Event::Halted => { break; }
+                event => { return Ok(event); }
             }
         }
         Ok(Event::Halted)
@@ -207,80 +756,14 @@ impl IntcodeMachine {
                     result.push(c);
                 }
                 Event::InputNeeded => {
-                    panic!();
+                    return Err(Error::InputNeeded);
                 }
+                Event::Breakpoint(_) | Event::Watch { .. } => {}
                 Event::Halted => { break; }
             }
         }
         Ok(result)
     }
-
-    pub fn get(&self, idx: Int) -> Result<Int, Error> {
-        let idx = usize::try_from(idx).map_err(|_| Error::NegativeIndex)?;
-        self.get_u(idx)
-    }
-
-    pub fn get_u(&self, idx: usize) -> Result<Int, Error> {
-        if idx >= self.data.len() {
-            return Ok(0);
-        }
-        self.data.get(idx).cloned().ok_or(Error::IndexOutOfBounds)
-    }
-
-    fn get_arg(&self, idx: Int, mode: Int) -> Result<Int, Error> {
-        match mode {
-            0 => self.get(idx),
-            1 => Ok(idx),  // immediate
-            2 => self.get(idx + self.rel_base),
-            _ => Err(Error::InvalidMode),
-        }
-    }
-
-    fn get_arg_addr(&self, idx: Int, mode: Int) -> Int {
-        match mode {
-            0 => idx,
-            2 => idx + self.rel_base,
-            _ => { panic!("Invalid mode for output argument {}", mode); },
-        }
-    }
-
-    fn get_input(&mut self) -> Result<Int, Error> {
-        if self.inputs.len() > 0 {
-            Ok(self.inputs.remove(0))
-        } else {
-            Err(Error::InputNeeded)
-        }
-    }
-
-    fn output(&mut self, val: Int) {
-        self.outputs.push(val);
-    }
-
-    pub fn set(&mut self, idx: Int, val: Int) {
-        let idx = usize::try_from(idx).unwrap();
-        if idx >= self.data.len() {
-            self.data.resize(idx+1, 0);
-        }
-        self.data[idx] = val;
-    }
-
-    pub fn get_data(&self, start: usize, len: usize) -> &[Int] {
-        &self.data[start..start+len]
-    }
-
-    pub fn send_input(&mut self, val: Int) {
-        self.inputs.push(val);
-    }
-
-    pub fn send_input_ascii(&mut self, input: &str) {
-        for c in input.chars() {
-            self.send_input(c as Int);
-        }
-    }
-
-    pub fn get_outputs(&self) -> &[Int] {
-        &self.outputs
-    }
 }
 
 #[cfg(test)]
@@ -290,7 +773,7 @@ mod tests {
     fn assert_state(memin: &[Int], memout: &[Int]) {
         let mut machine = IntcodeMachine::new(memin);
 
-        machine.run_until_halt();
+        machine.run_until_halt().unwrap();
         assert_eq!(machine.get_data(0, memout.len()), memout);
     }
 
@@ -299,7 +782,7 @@ mod tests {
         for val in input {
             machine.send_input(*val);
         }
-        machine.run_until_halt();
+        machine.run_until_halt().unwrap();
         assert_eq!(machine.get_data(0, memout.len()), memout);
         assert_eq!(machine.get_outputs(), output);
     }
@@ -339,12 +822,182 @@ mod tests {
         assert_inout(&[1102,34915192,34915192,7,4,7,99,0], &[], &[1219070632396864]);
         assert_inout(&[104,1125899906842624,99], &[], &[1125899906842624]);
     }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut machine = IntcodeMachine::new(&[104,1,104,2,104,3,99]);
+        machine.add_breakpoint(2);
+        machine.add_breakpoint(6);
+        assert_eq!(machine.run_until_event().unwrap(), Event::Output(1));
+        assert_eq!(machine.run_until_event().unwrap(), Event::Breakpoint(2));
+        assert_eq!(machine.run_until_event().unwrap(), Event::Output(2));
+        assert_eq!(machine.run_until_event().unwrap(), Event::Output(3));
+        assert_eq!(machine.run_until_event().unwrap(), Event::Breakpoint(6));
+        assert_eq!(machine.run_until_event().unwrap(), Event::Halted);
+
+        let mut machine = IntcodeMachine::new(&[104,1,104,2,104,3,99]);
+        machine.add_breakpoint(2);
+        assert!(machine.remove_breakpoint(2));
+        machine.add_breakpoint(4);
+        assert_eq!(machine.breakpoints().collect::<Vec<_>>(), vec![4]);
+        machine.run_until_halt().unwrap();
+        assert_eq!(machine.get_outputs(), &[1, 2, 3]);
+
+        // Resuming at a breakpoint which needs input runs the instruction
+        // once there is some, rather than stopping there again.
+        let mut machine = IntcodeMachine::new(&[3,5, 4,5, 99, 0]);
+        machine.add_breakpoint(0);
+        assert_eq!(machine.run_until_event().unwrap(), Event::Breakpoint(0));
+        assert_eq!(machine.run_until_event().unwrap(), Event::InputNeeded);
+        machine.send_input(7);
+        assert_eq!(machine.run_until_event().unwrap(), Event::Output(7));
+
+        // A jump back to the breakpoint stops there again.
+        let mut machine = IntcodeMachine::new(&[1105,1,0]);
+        machine.add_breakpoint(0);
+        machine.set_step_limit(Some(10));
+        assert_eq!(machine.run_until_event().unwrap(), Event::Breakpoint(0));
+        assert_eq!(machine.run_until_event().unwrap(), Event::Breakpoint(0));
+        assert_eq!(machine.steps(), 1);
+    }
+
+    #[test]
+    fn test_get_outputs() {
+        let mut machine = IntcodeMachine::new(&[104,1, 104,2, 104,3, 99]);
+        machine.run_until_halt().unwrap();
+        let outputs = |m: &IntcodeMachine| m.get_outputs();
+        assert_eq!(outputs(&machine), vec![1, 2, 3]);
+        assert_eq!(machine.take_outputs(), vec![1, 2, 3]);
+        assert!(outputs(&machine).is_empty());
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let prog = [1,9,10,11, 2,11,11,9, 99, 3,4,0];
+
+        let mut machine = IntcodeMachine::new(&prog);
+        machine.add_watchpoint(11, Watch::Write);
+        assert_eq!(machine.run_until_event().unwrap(),
+                   Event::Watch { pc: 0, addr: 11, access: Access::Write });
+        assert_eq!(machine.get(11).unwrap(), 7);
+        assert_eq!(machine.run_until_event().unwrap(), Event::Halted);
+
+        let mut machine = IntcodeMachine::new(&prog);
+        machine.add_watchpoint(9, Watch::ReadWrite);
+        assert_eq!(machine.run_until_event().unwrap(),
+                   Event::Watch { pc: 0, addr: 9, access: Access::Read });
+        assert_eq!(machine.run_until_event().unwrap(),
+                   Event::Watch { pc: 4, addr: 9, access: Access::Write });
+        assert_eq!(machine.get(9).unwrap(), 49);
+        assert!(machine.remove_watchpoint(9));
+        assert_eq!(machine.run_until_event().unwrap(), Event::Halted);
+    }
+
+    fn assert_fault(memin: &[Int], input: &[Int], error: Error) {
+        let mut machine = IntcodeMachine::new(memin);
+        for val in input {
+            machine.send_input(*val);
+        }
+        assert_eq!(machine.run_until_halt(), Err(error));
+    }
+
+    #[test]
+    fn test_faults() {
+        assert_fault(&[1,0,0,0,42], &[], Error::UnknownInstruction { pc: 4, insn: 42 });
+        assert_fault(&[1101,Int::MAX,1,0,99], &[],
+                     Error::Overflow { pc: 0, insn: 1101 });
+        assert_fault(&[1102,Int::MIN,-1,0,99], &[],
+                     Error::Overflow { pc: 0, insn: 1102 });
+        assert_fault(&[109,Int::MAX,109,1,99], &[],
+                     Error::Overflow { pc: 2, insn: 109 });
+        assert_fault(&[1105,1,-7], &[],
+                     Error::NegativeJump { pc: 0, insn: 1105, target: -7 });
+        assert_fault(&[11101,1,1,5,99], &[],
+                     Error::InvalidWriteMode { pc: 0, insn: 11101 });
+        assert_fault(&[3,-2,99], &[5],
+                     Error::NegativeAddress { pc: 0, insn: 3, addr: -2 });
+        assert_fault(&[204,-1,99], &[],
+                     Error::NegativeAddress { pc: 0, insn: 204, addr: -1 });
+        assert_fault(&[304,0,99], &[], Error::InvalidMode { pc: 0, insn: 304 });
+
+        let mut machine = IntcodeMachine::new(&[99]);
+        assert_eq!(machine.set(-1, 0), Err(Error::NegativeIndex));
+        assert_eq!(machine.run_ascii_fixed_input(""), Ok(vec![]));
+        let mut machine = IntcodeMachine::new(&[3,0,99]);
+        assert_eq!(machine.run_ascii_fixed_input(""), Err(Error::InputNeeded));
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let forever = [1101,1,2,7,1105,1,0,0];
+        let mut machine = IntcodeMachine::new(&forever);
+        machine.set_step_limit(Some(5));
+        assert_eq!(machine.run_until_halt(), Err(Error::StepLimit { pc: 4 }));
+        assert_eq!(machine.steps(), 5);
+        machine.set_step_budget(2);
+        assert_eq!(machine.run_until_output(), Err(Error::StepLimit { pc: 4 }));
+        assert_eq!(machine.steps(), 7);
+
+        // Waiting for input doesn't use up the budget.
+        let mut machine = IntcodeMachine::new(&[3,0,99]);
+        machine.set_step_limit(Some(1));
+        assert_eq!(machine.run_until_event(), Ok(Event::InputNeeded));
+        machine.send_input(1);
+        assert_eq!(machine.run_until_event(), Err(Error::StepLimit { pc: 2 }));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let forever = [1101,1,2,7,1105,1,0,0];
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut machine = IntcodeMachine::new(&forever);
+        machine.set_cancel_flag(Some(flag.clone()));
+        let mut fork = machine.clone();
+        let handle = std::thread::spawn(move || machine.run_until_halt());
+        flag.store(true, Ordering::Relaxed);
+        assert!(matches!(handle.join().unwrap(), Err(Error::Cancelled { .. })));
+        assert!(matches!(fork.run_ascii(""), Err(Error::Cancelled { pc: 0 })));
+    }
+
+    #[test]
+    fn test_halt_before_data() {
+        // Parameters which an instruction doesn't use are never read.
+        assert_inout(&[104,7,99,-5,-6], &[], &[7]);
+    }
+
+    #[test]
+    fn test_ascii_io() {
+        // Echo every input
+        let mut machine = IntcodeMachine::new(&[3,7,4,7,1105,1,0,0]);
+        let mut output = Vec::new();
+        let event = machine.run_ascii_io(&b"hi\nthere\n"[..], &mut output).unwrap();
+        assert_eq!(event, Event::InputNeeded);
+        assert_eq!(output, b"hi\nthere\n");
+    }
+
+    #[test]
+    fn test_step_event() {
+        let mut machine = IntcodeMachine::new(&[3,9,4,9,1,9,9,9,99,0]);
+        machine.add_breakpoint(2);
+        machine.add_watchpoint(9, Watch::Write);
+        assert_eq!(machine.step_event().unwrap(), Some(Event::InputNeeded));
+        machine.send_input(5);
+        assert_eq!(machine.step_event().unwrap(),
+                   Some(Event::Watch { pc: 0, addr: 9, access: Access::Write }));
+        assert_eq!(machine.step_event().unwrap(), Some(Event::Output(5)));
+        assert_eq!(machine.pc(), 4);
+        assert_eq!(machine.step_event().unwrap(),
+                   Some(Event::Watch { pc: 4, addr: 9, access: Access::Write }));
+        assert_eq!(machine.step_event().unwrap(), Some(Event::Halted));
+        assert!(machine.is_halted());
+        assert_eq!(machine.get(9).unwrap(), 10);
+    }
 }
 
 pub fn run_with_input(data: &[Int], noun: Int, verb: Int) -> Result<Int, Error> {
     let mut machine = IntcodeMachine::new(data);
-    machine.set(1, noun);
-    machine.set(2, verb);
+    machine.set(1, noun)?;
+    machine.set(2, verb)?;
     machine.run_until_halt()?;
     machine.get(0)
 }
@@ -0,0 +1,124 @@
+use super::{Int, IntcodeMachine, Event, Error};
+
+// Machines wired in series: each is first given its phase setting, then
+// each output is passed on as the next machine's input.  With feedback
+// the last machine's output goes back to the first, until all halt.  A
+// machine which halts without output ends the chain there, since the
+// machines after it have no signal to work on.
+
+fn run_to_output(machine: &mut IntcodeMachine) -> Result<Option<Int>, Error> {
+    loop {
+        match machine.run_until_event()? {
+            Event::Output(val) => return Ok(Some(val)),
+            Event::Halted => return Ok(None),
+            Event::InputNeeded => return Err(Error::InputNeeded),
+            Event::Breakpoint(_) | Event::Watch { .. } => {}
+        }
+    }
+}
+
+// Returns the last signal output by the final machine, or NoOutput if
+// the chain ended before it gave one.
+pub fn run_amplifiers(program: &[Int], phases: &[Int], input: Int, feedback: bool) -> Result<Int, Error> {
+    let mut machines: Vec<_> = phases.iter().map(|&phase| {
+        let mut machine = IntcodeMachine::new(program);
+        machine.send_input(phase);
+        machine
+    }).collect();
+    let last_idx = machines.len().checked_sub(1).ok_or(Error::NoOutput)?;
+
+    let mut signal = input;
+    let mut result = None;
+    'rounds: loop {
+        for (idx, machine) in machines.iter_mut().enumerate() {
+            if machine.is_halted() {
+                continue;
+            }
+            machine.send_input(signal);
+            match run_to_output(machine)? {
+                Some(val) => signal = val,
+                None => break 'rounds,
+            }
+            if idx == last_idx {
+                result = Some(signal);
+            }
+        }
+        if !feedback || machines.iter().all(|m| m.is_halted()) {
+            break;
+        }
+    }
+    result.ok_or(Error::NoOutput)
+}
+
+pub fn permutations(values: &[Int]) -> Vec<Vec<Int>> {
+    if values.len() <= 1 {
+        return vec![values.to_vec()];
+    }
+    let mut result = Vec::new();
+    for i in 0..values.len() {
+        let mut rest = values.to_vec();
+        let first = rest.remove(i);
+        for mut perm in permutations(&rest) {
+            perm.insert(0, first);
+            result.push(perm);
+        }
+    }
+    result
+}
+
+// Try every ordering of the phase values, returning the highest final
+// signal and the phases which gave it.
+pub fn best_phases(program: &[Int], phase_values: &[Int], input: Int, feedback: bool)
+    -> Result<(Int, Vec<Int>), Error>
+{
+    let mut best: Option<(Int, Vec<Int>)> = None;
+    for phases in permutations(phase_values) {
+        let signal = run_amplifiers(program, &phases, input, feedback)?;
+        if best.as_ref().is_none_or(|(b, _)| signal > *b) {
+            best = Some((signal, phases));
+        }
+    }
+    best.ok_or(Error::NoOutput)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_series() {
+        let prog = [3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0];
+        assert_eq!(run_amplifiers(&prog, &[4,3,2,1,0], 0, false).unwrap(), 43210);
+        assert_eq!(best_phases(&prog, &[0,1,2,3,4], 0, false).unwrap(),
+                   (43210, vec![4,3,2,1,0]));
+
+        let prog = [3,31,3,32,1002,32,10,32,1001,31,-2,31,1007,31,0,33,
+                    1002,33,7,33,1,33,31,31,1,32,31,31,4,31,99,0,0,0];
+        assert_eq!(best_phases(&prog, &[0,1,2,3,4], 0, false).unwrap(),
+                   (65210, vec![1,0,4,3,2]));
+    }
+
+    #[test]
+    fn test_feedback() {
+        let prog = [3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,
+                    27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5];
+        assert_eq!(run_amplifiers(&prog, &[9,8,7,6,5], 0, true).unwrap(), 139629729);
+        assert_eq!(best_phases(&prog, &[5,6,7,8,9], 0, true).unwrap(),
+                   (139629729, vec![9,8,7,6,5]));
+    }
+
+    #[test]
+    fn test_halt_without_output() {
+        // Adds one to its input, except that phase 1 halts straight away.
+        let prog = [3,18, 3,19, 1008,18,1,20, 1005,20,17, 101,1,19,19, 4,19, 99, 0,0,0];
+        assert_eq!(run_amplifiers(&prog, &[0,0,0], 5, false), Ok(8));
+        assert_eq!(run_amplifiers(&prog, &[0,1,0], 5, false), Err(Error::NoOutput));
+        assert_eq!(run_amplifiers(&prog, &[0,0,1], 5, false), Err(Error::NoOutput));
+    }
+
+    #[test]
+    fn test_permutations() {
+        assert_eq!(permutations(&[1, 2, 3]).len(), 6);
+        assert_eq!(permutations(&[]), vec![Vec::<Int>::new()]);
+    }
+}
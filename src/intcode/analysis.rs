@@ -0,0 +1,320 @@
+use std::collections::{BTreeMap, BTreeSet};
+use super::{Mode, IntcodeMachine, Input, Output};
+use super::disasm::{Line, decode_line};
+use super::memory::Words;
+
+// Static analysis of a program without running it: the code reachable
+// from some entry points, split into basic blocks with the control flow
+// between them.  Jumps whose target is read from memory (indirect jumps)
+// can't be followed, and neither can writes through relative addresses,
+// so code reached only that way shows up as unreachable.
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Exit {
+    // Runs on into the block starting here
+    Next(usize),
+    Jump(usize),
+    // Conditional jump: the target, then the next instruction
+    Branch(usize, usize),
+    // A jump whose target isn't known, and the next instruction if it
+    // might not be taken.
+    Indirect(Option<usize>),
+    Halt,
+    // Not a valid instruction (or a jump to a negative address)
+    Fault,
+}
+
+impl Exit {
+    pub fn successors(&self) -> Vec<usize> {
+        match *self {
+            Exit::Next(next) | Exit::Jump(next) => vec![next],
+            Exit::Branch(target, next) => vec![target, next],
+            Exit::Indirect(next) => next.into_iter().collect(),
+            Exit::Halt | Exit::Fault => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Block {
+    pub start: usize,
+    // One past the last word of the last instruction
+    pub end: usize,
+    pub lines: Vec<Line>,
+    pub exit: Exit,
+}
+
+#[derive(Debug,Clone,Default)]
+pub struct Analysis {
+    pub blocks: BTreeMap<usize, Block>,
+    // Addresses of indirect jumps
+    pub indirect_jumps: Vec<usize>,
+    // Instructions which write into reachable code: (pc, address written)
+    pub self_modifying: Vec<(usize, usize)>,
+    // Ranges (start, end) not reached which contain something that
+    // decodes as an instruction.  These may just be data.
+    pub unreachable: Vec<(usize, usize)>,
+}
+
+// Where control can go after the instruction.  A jump whose condition is
+// immediate always or never goes.
+fn exit_of(line: &Line) -> Exit {
+    let next = line.addr() + line.size();
+    let (insn, args) = match line {
+        Line::Insn { insn, args, .. } => (insn, args),
+        Line::Data { .. } => return Exit::Fault,
+    };
+    match insn.op.opcode {
+        5 | 6 => {
+            let always = match insn.modes[0] {
+                Mode::Immediate if (args[0] != 0) != (insn.op.opcode == 5) => return Exit::Next(next),
+                Mode::Immediate => true,
+                _ => false,
+            };
+            let target = match insn.modes[1] {
+                Mode::Immediate => usize::try_from(args[1]).ok(),
+                _ => return Exit::Indirect(if always { None } else { Some(next) }),
+            };
+            match (target, always) {
+                (Some(target), true) => Exit::Jump(target),
+                (Some(target), false) => Exit::Branch(target, next),
+                (None, true) => Exit::Fault,
+                (None, false) => Exit::Next(next),
+            }
+        }
+        99 => Exit::Halt,
+        _ => Exit::Next(next),
+    }
+}
+
+pub fn analyse<M: Words + ?Sized>(mem: &M, entries: &[usize]) -> Analysis {
+    let mut result = Analysis::default();
+
+    // Find everything reachable, and where blocks must start.
+    let mut reached = BTreeMap::new();
+    let mut leaders: BTreeSet<usize> = entries.iter().cloned().collect();
+    let mut todo: Vec<usize> = entries.to_vec();
+    while let Some(pc) = todo.pop() {
+        if reached.contains_key(&pc) {
+            continue;
+        }
+        let line = decode_line(mem, pc);
+        let exit = exit_of(&line);
+        match exit {
+            Exit::Jump(_) | Exit::Branch(..) => leaders.extend(exit.successors()),
+            Exit::Indirect(next) => {
+                result.indirect_jumps.push(pc);
+                leaders.extend(next);
+            }
+            _ => {}
+        }
+        todo.extend(exit.successors());
+        reached.insert(pc, line);
+    }
+    result.indirect_jumps.sort_unstable();
+
+    for &start in &leaders {
+        let mut lines = Vec::new();
+        let mut pc = start;
+        let exit = loop {
+            let line = reached[&pc].clone();
+            let exit = exit_of(&line);
+            lines.push(line);
+            match exit {
+                Exit::Next(next) if !leaders.contains(&next) => pc = next,
+                exit => break exit,
+            }
+        };
+        let end = lines.last().map_or(start, |l| l.addr() + l.size());
+        result.blocks.insert(start, Block { start, end, lines, exit });
+    }
+
+    let code: BTreeSet<usize> = reached.values()
+                                       .flat_map(|l| l.addr()..l.addr() + l.size())
+                                       .collect();
+    for line in reached.values() {
+        if let Line::Insn { addr, insn, args } = line {
+            let last = insn.op.params.wrapping_sub(1);
+            if insn.op.writes && insn.modes[last] == Mode::Position {
+                if let Some(target) = usize::try_from(args[last]).ok().filter(|t| code.contains(t)) {
+                    result.self_modifying.push((*addr, target));
+                }
+            }
+        }
+    }
+
+    // Memory outside the loaded ranges is zero, which never decodes.
+    for (low, end) in mem.loaded() {
+        let mut addr = low;
+        while addr < end {
+            if code.contains(&addr) {
+                addr += 1;
+                continue;
+            }
+            let start = addr;
+            while addr < end && !code.contains(&addr) {
+                addr += 1;
+            }
+            if decodes_any(mem, start, addr) {
+                result.unreachable.push((start, addr));
+            }
+        }
+    }
+    result
+}
+
+// Whether any instruction lies wholly within start..end.
+fn decodes_any<M: Words + ?Sized>(mem: &M, start: usize, end: usize) -> bool {
+    let mut addr = start;
+    while addr < end {
+        let line = decode_line(mem, addr);
+        if let Line::Insn { .. } = line {
+            if addr + line.size() <= end {
+                return true;
+            }
+            addr += 1;
+        } else {
+            addr += line.size();
+        }
+    }
+    false
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl Analysis {
+    // The block containing the instruction at `addr`, if reachable.
+    pub fn block_at(&self, addr: usize) -> Option<&Block> {
+        self.blocks.range(..=addr)
+                   .next_back()
+                   .map(|(_, block)| block)
+                   .filter(|block| block.lines.iter().any(|l| l.addr() == addr))
+    }
+
+    // The control flow graph in Graphviz format, one node per block.
+    pub fn to_dot(&self) -> String {
+        let mut result = String::from("digraph intcode {\n    node [shape=box, fontname=\"monospace\"];\n");
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for line in &block.lines {
+                label += &dot_escape(&format!("{:5}: {}", line.addr(), line));
+                label += "\\l";
+            }
+            result += &format!("    b{} [label=\"{}\"];\n", block.start, label);
+        }
+        let mut indirect = false;
+        for block in self.blocks.values() {
+            let from = block.start;
+            match block.exit {
+                Exit::Next(to) | Exit::Jump(to) => result += &format!("    b{} -> b{};\n", from, to),
+                Exit::Branch(target, next) => {
+                    result += &format!("    b{} -> b{} [label=\"taken\"];\n", from, target);
+                    result += &format!("    b{} -> b{} [label=\"not taken\"];\n", from, next);
+                }
+                Exit::Indirect(next) => {
+                    indirect = true;
+                    result += &format!("    b{} -> indirect [style=dashed];\n", from);
+                    if let Some(next) = next {
+                        result += &format!("    b{} -> b{} [label=\"not taken\"];\n", from, next);
+                    }
+                }
+                Exit::Halt | Exit::Fault => {}
+            }
+        }
+        if indirect {
+            result += "    indirect [label=\"?\", shape=circle];\n";
+        }
+        result += "}\n";
+        result
+    }
+}
+
+impl<I: Input, O: Output> IntcodeMachine<I, O> {
+    // Analyse the code reachable from the current pc.
+    pub fn analyse(&self) -> Analysis {
+        analyse(&self.data, &[self.pc])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::asm::assemble;
+
+    #[test]
+    fn test_blocks() {
+        // Counts n down, then jumps through a pointer to the end.
+        let prog = assemble(r#"
+                    add  #3, #0, n
+            loop:   add  total, n, total
+                    add  n, #-1, n
+                    jt   n, #loop
+                    out  total
+                    jt   #1, ptr
+                    add  #1, #2, total   ; never reached
+            end:    hlt
+            n:      data 0
+            total:  data 0
+            ptr:    data end
+        "#).unwrap();
+        let analysis = analyse(&prog, &[0]);
+        let starts: Vec<_> = analysis.blocks.keys().cloned().collect();
+        assert_eq!(starts, vec![0, 4, 15]);
+        assert_eq!(analysis.blocks[&0].exit, Exit::Next(4));
+        assert_eq!(analysis.blocks[&4].exit, Exit::Branch(4, 15));
+        assert_eq!(analysis.blocks[&4].lines.len(), 3);
+        assert_eq!(analysis.blocks[&15].exit, Exit::Indirect(None));
+        assert_eq!(analysis.indirect_jumps, vec![17]);
+        assert_eq!(analysis.unreachable, vec![(20, 28)]);
+        assert!(analysis.self_modifying.is_empty());
+        assert_eq!(analysis.block_at(8).map(|b| b.start), Some(4));
+        assert_eq!(analysis.block_at(9), None);
+
+        // Following the pointer by hand finds the rest.
+        let analysis = analyse(&prog, &[0, 24]);
+        assert_eq!(analysis.blocks[&24].exit, Exit::Halt);
+        assert_eq!(analysis.unreachable, vec![(20, 24)]);
+    }
+
+    #[test]
+    fn test_self_modifying() {
+        let prog = assemble(r#"
+            loop:   add  total, #1, total
+                    add  loop + 2, #1, loop + 2
+                    jf   #0, #loop
+            total:  data 0
+        "#).unwrap();
+        let analysis = analyse(&prog, &[0]);
+        assert_eq!(analysis.self_modifying, vec![(4, 2)]);
+        assert_eq!(analysis.blocks[&0].exit, Exit::Jump(0));
+        // Running off the end into data is a fault.
+        let analysis = analyse(&[1101, 1, 2, 3], &[0]);
+        assert_eq!(analysis.blocks[&0].exit, Exit::Fault);
+        assert_eq!(analysis.blocks[&0].lines.len(), 2);
+    }
+
+    #[test]
+    fn test_dot() {
+        let prog = [1105, 1, 4, 99, 6, 0, 3, 99];
+        let dot = analyse(&prog, &[0]).to_dot();
+        assert!(dot.starts_with("digraph intcode {\n"));
+        assert!(dot.contains("    b0 [label=\"    0: jt  #1, #4\\l\"];\n"));
+        assert!(dot.contains("    b0 -> b4;\n"));
+        assert!(dot.contains("    b4 -> indirect [style=dashed];\n"));
+        assert!(dot.contains("    b4 -> b7 [label=\"not taken\"];\n"));
+    }
+
+    #[test]
+    fn test_high_write() {
+        // Leaves a hlt far beyond the program; only the memory actually
+        // written is scanned for it.  The analysis starts from the hlt at
+        // pc, so the add is unreachable too.
+        let mut machine = IntcodeMachine::new(&[1101, 0, 99, 1 << 40, 99]);
+        machine.run_until_halt().unwrap();
+        let analysis = machine.analyse();
+        assert_eq!(analysis.blocks[&4].exit, Exit::Halt);
+        assert_eq!(analysis.unreachable, vec![(0, 4), (1 << 40, (1 << 40) + 1)]);
+    }
+}
@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use super::{Int, Mode, op_by_mnemonic};
+
+// Assembler for a small textual Intcode language.  Each line looks like:
+//
+//   label:  mnemonic operand, operand, ...   ; comment
+//
+// Operands are `expr` (position mode), `#expr` (immediate mode) or `@expr`
+// (relative mode), where `expr` is a number or label followed by any
+// number of `+term` or `-term`.  A label evaluates to its address.
+//
+// `data` emits its operands directly as words; it also accepts string
+// literals, which emit one word per character.
+
+#[derive(Debug)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::error::Error for AsmError {
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Debug)]
+enum Term {
+    Num(Int),
+    Label(String),
+}
+
+#[derive(Debug)]
+struct Expr {
+    terms: Vec<(bool, Term)>,  // (negated, term)
+}
+
+#[derive(Debug)]
+enum Item {
+    Insn { opcode: Int, operands: Vec<(Mode, Expr)> },
+    Data(Vec<Expr>),
+}
+
+impl Item {
+    fn len(&self) -> usize {
+        match self {
+            Item::Insn { operands, .. } => 1 + operands.len(),
+            Item::Data(exprs) => exprs.len(),
+        }
+    }
+}
+
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Remove any comment, respecting string literals.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == ';' {
+            return &line[..i];
+        }
+    }
+    line
+}
+
+// Split operands on commas which aren't inside string literals.
+fn split_operands(s: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == ',' {
+            result.push(s[start..i].trim());
+            start = i + 1;
+        }
+    }
+    let last = s[start..].trim();
+    if !last.is_empty() || !result.is_empty() {
+        result.push(last);
+    }
+    result
+}
+
+fn parse_string(s: &str) -> Result<Vec<Int>, String> {
+    let inner = s.strip_prefix('"')
+                 .and_then(|s| s.strip_suffix('"'))
+                 .ok_or_else(|| format!("Bad string literal {}", s))?;
+    let mut result = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = if c == '\\' {
+            match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('\\') => '\\',
+                Some('"') => '"',
+                other => return Err(format!("Bad escape {:?} in string", other)),
+            }
+        } else {
+            c
+        };
+        result.push(c as Int);
+    }
+    Ok(result)
+}
+
+fn parse_term(s: &str) -> Result<Term, String> {
+    let s = s.trim();
+    if let Ok(n) = s.parse::<Int>() {
+        Ok(Term::Num(n))
+    } else if is_ident(s) {
+        Ok(Term::Label(s.into()))
+    } else {
+        Err(format!("Bad operand term {:?}", s))
+    }
+}
+
+fn parse_expr(s: &str) -> Result<Expr, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err("Missing operand".into());
+    }
+    let mut terms = Vec::new();
+    let mut negated = false;
+    let mut start = 0;
+    // A leading sign belongs to the first term (so it can be a negative number).
+    for (i, c) in s.char_indices().skip(1) {
+        if c == '+' || c == '-' {
+            terms.push((negated, parse_term(&s[start..i])?));
+            negated = c == '-';
+            start = i + 1;
+        }
+    }
+    terms.push((negated, parse_term(&s[start..])?));
+    Ok(Expr { terms })
+}
+
+fn parse_operand(s: &str) -> Result<(Mode, Expr), String> {
+    if let Some(rest) = s.strip_prefix('#') {
+        Ok((Mode::Immediate, parse_expr(rest)?))
+    } else if let Some(rest) = s.strip_prefix('@') {
+        Ok((Mode::Relative, parse_expr(rest)?))
+    } else {
+        Ok((Mode::Position, parse_expr(s)?))
+    }
+}
+
+fn parse_item(mnemonic: &str, operands: &[&str]) -> Result<Item, String> {
+    if mnemonic == "data" {
+        let mut exprs = Vec::new();
+        for operand in operands {
+            if operand.starts_with('"') {
+                for val in parse_string(operand)? {
+                    exprs.push(Expr { terms: vec![(false, Term::Num(val))] });
+                }
+            } else {
+                exprs.push(parse_expr(operand)?);
+            }
+        }
+        return Ok(Item::Data(exprs));
+    }
+    let op = op_by_mnemonic(mnemonic)
+                 .ok_or_else(|| format!("Unknown mnemonic {:?}", mnemonic))?;
+    if operands.len() != op.params {
+        return Err(format!("{} takes {} operands, found {}",
+                           mnemonic, op.params, operands.len()));
+    }
+    let operands = operands.iter()
+                           .map(|s| parse_operand(s))
+                           .collect::<Result<Vec<_>, _>>()?;
+    if op.writes && operands.last().unwrap().0 == Mode::Immediate {
+        return Err(format!("{} cannot write to an immediate operand", mnemonic));
+    }
+    Ok(Item::Insn { opcode: op.opcode, operands })
+}
+
+fn eval(expr: &Expr, labels: &HashMap<String, Int>) -> Result<Int, String> {
+    let mut result: Int = 0;
+    for (negated, term) in &expr.terms {
+        let val = match term {
+            Term::Num(n) => *n,
+            Term::Label(name) => *labels.get(name)
+                                        .ok_or_else(|| format!("Unknown label {}", name))?,
+        };
+        result = if *negated {
+            result.checked_sub(val)
+        } else {
+            result.checked_add(val)
+        }.ok_or_else(|| "Overflow in operand".to_string())?;
+    }
+    Ok(result)
+}
+
+pub fn assemble(source: &str) -> Result<Vec<Int>, AsmError> {
+    let mut labels = HashMap::new();
+    let mut items = Vec::new();
+    let mut addr = 0;
+
+    for (lineno, line) in source.lines().enumerate() {
+        let err = |message| AsmError { line: lineno + 1, message };
+        let mut line = strip_comment(line).trim();
+        while let Some(colon) = line.find(':') {
+            let label = line[..colon].trim();
+            if !is_ident(label) {
+                break;
+            }
+            if labels.insert(label.to_string(), addr as Int).is_some() {
+                return Err(err(format!("Duplicate label {}", label)));
+            }
+            line = line[colon+1..].trim();
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let (mnemonic, rest) = match line.find(char::is_whitespace) {
+            Some(pos) => (&line[..pos], &line[pos..]),
+            None => (line, ""),
+        };
+        let item = parse_item(mnemonic, &split_operands(rest)).map_err(err)?;
+        addr += item.len();
+        items.push((lineno + 1, item));
+    }
+
+    let mut result = Vec::with_capacity(addr);
+    for (line, item) in items {
+        let err = |message| AsmError { line, message };
+        match item {
+            Item::Insn { opcode, operands } => {
+                let mut insn = opcode;
+                let mut scale = 100;
+                for (mode, _) in &operands {
+                    insn += mode.digit() * scale;
+                    scale *= 10;
+                }
+                result.push(insn);
+                for (_, expr) in &operands {
+                    result.push(eval(expr, &labels).map_err(err)?);
+                }
+            }
+            Item::Data(exprs) => {
+                for expr in &exprs {
+                    result.push(eval(expr, &labels).map_err(err)?);
+                }
+            }
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::IntcodeMachine;
+
+    #[test]
+    fn test_encoding() {
+        assert_eq!(assemble("add 0, 0, 0\nhlt").unwrap(), vec![1, 0, 0, 0, 99]);
+        assert_eq!(assemble("mul #34915192, #34915192, 7\nout 7\nhlt\ndata 0").unwrap(),
+                   vec![1102, 34915192, 34915192, 7, 4, 7, 99, 0]);
+        assert_eq!(assemble("arb #1\nout @-1").unwrap(), vec![109, 1, 204, -1]);
+    }
+
+    #[test]
+    fn test_labels() {
+        let prog = assemble(r#"
+            ; Echo inputs until a zero is read
+            loop:   in   val
+                    jf   val, #end      ; stop on zero
+                    out  val
+                    jt   #1, #loop
+            end:    hlt
+            val:    data 0
+        "#).unwrap();
+        assert_eq!(prog, vec![3, 11, 1006, 11, 10, 4, 11, 1105, 1, 0, 99, 0]);
+        let mut machine = IntcodeMachine::new(&prog);
+        for val in &[3, 4, 0] {
+            machine.send_input(*val);
+        }
+        machine.run_until_halt().unwrap();
+        assert_eq!(machine.get_outputs(), &[3, 4]);
+    }
+
+    #[test]
+    fn test_data() {
+        assert_eq!(assemble("a: data a+2, b-1, \"A;,\\n\"\nb: data -5").unwrap(),
+                   vec![2, 5, 65, 59, 44, 10, -5]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(assemble("add 1, 2").unwrap_err().line, 1);
+        assert_eq!(assemble("\nfoo 1").unwrap_err().line, 2);
+        assert_eq!(assemble("in #3").unwrap_err().line, 1);
+        assert_eq!(assemble("out missing").unwrap_err().line, 1);
+        assert_eq!(assemble("x: data 0\nx: data 1").unwrap_err().line, 2);
+    }
+}
@@ -0,0 +1,310 @@
+use std::marker::PhantomData;
+use super::{Int, Mode, IntcodeMachine, Input, Output, Error, Word, decode, to_address, jump_target};
+
+// An alternative to interpreting each instruction word afresh: the first
+// time an instruction is executed it is decoded, along with its
+// arguments, and kept by address.  Any write within an entry's words
+// drops it, so self-modifying code still behaves.  Only instructions
+// which decode strictly are cached; anything else goes through the
+// interpreter, which reports the same faults as ever.
+//
+// Entries hold no words, only Ints and addresses, so that using one
+// costs a copy whatever the word type.  Immediate values are read from
+// the instruction itself, as the interpreter reads them.
+
+// Highest address whose instruction is cached.
+const MAX_CACHED: usize = 1 << 20;
+// Longest instruction, so the furthest a write can be from its start.
+const MAX_SIZE: usize = 4;
+
+// Operands with their modes resolved.  Position arguments which are
+// negative would fault, so such instructions aren't cached, nor are
+// relative ones whose offset doesn't fit an Int.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+enum Operand {
+    // The address of the value
+    Immediate(usize),
+    Position(usize),
+    Relative(Int),
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+struct Decoded {
+    insn: Int,
+    opcode: u8,
+    operands: [Operand; 3],
+}
+
+#[derive(Debug,Clone,Default)]
+pub struct DecodeCache<W = Int> {
+    entries: Vec<Option<Decoded>>,
+    word: PhantomData<fn() -> W>,
+}
+
+impl<W: Word> DecodeCache<W> {
+    pub fn new() -> DecodeCache<W> {
+        DecodeCache { entries: Vec::new(), word: PhantomData }
+    }
+
+    fn get(&self, pc: usize) -> Option<Decoded> {
+        self.entries.get(pc).cloned().flatten()
+    }
+
+    fn insert(&mut self, pc: usize, decoded: Decoded) {
+        if pc >= MAX_CACHED {
+            return;
+        }
+        if pc >= self.entries.len() {
+            self.entries.resize(pc + 1, None);
+        }
+        self.entries[pc] = Some(decoded);
+    }
+
+    // Forget any instruction which includes `addr`.
+    pub fn invalidate(&mut self, addr: usize) {
+        if addr >= self.entries.len() + MAX_SIZE {
+            return;
+        }
+        let start = addr.saturating_sub(MAX_SIZE - 1);
+        let end = self.entries.len().min(addr.saturating_add(1));
+        for entry in self.entries.iter_mut().take(end).skip(start) {
+            *entry = None;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    // Number of instructions currently cached.
+    pub fn len(&self) -> usize {
+        self.entries.iter().filter(|e| e.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<I: Input<W>, O: Output<W>, W: Word> IntcodeMachine<I, O, W> {
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.cache = if enabled { Some(DecodeCache::new()) } else { None };
+    }
+
+    pub fn decode_cache(&self) -> Option<&DecodeCache<W>> {
+        self.cache.as_ref()
+    }
+
+    fn fetch_decoded(&mut self) -> Option<Decoded> {
+        let pc = self.pc;
+        let cache = self.cache.as_mut()?;
+        if let Some(decoded) = cache.get(pc) {
+            return Some(decoded);
+        }
+        let insn = self.data.get(pc).to_int()?;
+        let info = decode(insn)?;
+        // An instruction running off the end of memory faults, uncached.
+        pc.checked_add(info.size())?;
+        let mut operands = [Operand::Position(0), Operand::Position(0), Operand::Position(0)];
+        for (i, operand) in operands.iter_mut().enumerate().take(info.op.params) {
+            let addr = pc + 1 + i;
+            let arg = self.data.get(addr);
+            *operand = match info.modes[i] {
+                Mode::Immediate => Operand::Immediate(addr),
+                Mode::Position => Operand::Position(arg.to_usize()?),
+                Mode::Relative => Operand::Relative(arg.to_int()?),
+            };
+        }
+        let decoded = Decoded { insn, opcode: info.op.opcode as u8, operands };
+        cache.insert(pc, decoded);
+        Some(decoded)
+    }
+
+    // Faults are reported with the instruction as a word, which is only
+    // made when needed.
+    fn insn_word(d: &Decoded) -> W {
+        W::from_int(d.insn)
+    }
+
+    fn cached_addr(&self, d: &Decoded, n: usize) -> Result<usize, Error<W>> {
+        let pc = self.pc;
+        match d.operands[n] {
+            Operand::Position(addr) => Ok(addr),
+            Operand::Relative(offset) => {
+                let addr = self.rel_base.to_int().and_then(|base| offset.checked_add(base));
+                if let Some(addr) = addr.and_then(|a| usize::try_from(a).ok()) {
+                    return Ok(addr);
+                }
+                // Faults, and bases too big for an Int, as the interpreter has them
+                let insn = Self::insn_word(d);
+                let addr = W::from_int(offset).checked_add(&self.rel_base)
+                                              .ok_or_else(|| Error::Overflow { pc, insn: insn.clone() })?;
+                to_address(pc, &insn, addr)
+            }
+            Operand::Immediate(_) => Err(Error::InvalidWriteMode { pc, insn: Self::insn_word(d) }),
+        }
+    }
+
+    fn cached_read(&self, d: &Decoded, n: usize) -> Result<W, Error<W>> {
+        let val = match d.operands[n] {
+            Operand::Immediate(addr) => self.data.get(addr),
+            Operand::Position(addr) => self.load(addr)?,
+            Operand::Relative(_) => self.load(self.cached_addr(d, n)?)?,
+        };
+        self.note_read(&val);
+        Ok(val)
+    }
+
+    // Execute the instruction at pc from the cache, returning None if it
+    // can't be cached.
+    pub(super) fn execute_cached(&mut self) -> Option<Result<(), Error<W>>> {
+        let d = self.fetch_decoded()?;
+        Some(self.execute_decoded(&d))
+    }
+
+    fn execute_decoded(&mut self, d: &Decoded) -> Result<(), Error<W>> {
+        let pc = self.pc;
+        let overflow = || Error::Overflow { pc, insn: Self::insn_word(d) };
+        match d.opcode {
+            1 | 2 | 7 | 8 => {
+                let val0 = self.cached_read(d, 0)?;
+                let val1 = self.cached_read(d, 1)?;
+                let result = match d.opcode {
+                    1 => val0.checked_add(&val1).ok_or_else(overflow)?,
+                    2 => val0.checked_mul(&val1).ok_or_else(overflow)?,
+                    7 => W::from_int((val0 < val1) as Int),
+                    _ => W::from_int((val0 == val1) as Int),
+                };
+                let addr = self.cached_addr(d, 2)?;
+                self.store(addr, result)?;
+                self.pc += 4;
+            }
+            3 => {
+                let addr = self.cached_addr(d, 0)?;
+                let val = self.get_input()?;
+                self.store(addr, val)?;
+                self.pc += 2;
+            }
+            4 => {
+                let val0 = self.cached_read(d, 0)?;
+                self.output(val0);
+                self.pc += 2;
+            }
+            5 | 6 => {
+                let val0 = self.cached_read(d, 0)?;
+                let val1 = self.cached_read(d, 1)?;
+                if val0.is_zero() != (d.opcode == 5) {
+                    self.pc = match val1.to_usize() {
+                        Some(target) => target,
+                        None => jump_target(pc, &Self::insn_word(d), val1)?,
+                    };
+                } else {
+                    self.pc += 3;
+                }
+            }
+            9 => {
+                let val0 = self.cached_read(d, 0)?;
+                self.rel_base = self.rel_base.checked_add(&val0).ok_or_else(overflow)?;
+                self.pc += 2;
+            }
+            _ => {
+                self.halted = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::asm::assemble;
+
+    // Run the program with and without the cache, checking that the
+    // results, outputs and final state all match.
+    fn assert_equivalent(program: &[Int], input: &[Int]) {
+        let run = |cached: bool| {
+            let mut machine = IntcodeMachine::new(program);
+            machine.set_decode_cache(cached);
+            for &val in input {
+                machine.send_input(val);
+            }
+            let result = machine.run_until_halt();
+            (result, machine.take_outputs(), machine.pc(), machine.rel_base(),
+             machine.get_data(0, machine.memory_len()))
+        };
+        assert_eq!(run(true), run(false));
+    }
+
+    #[test]
+    fn test_equivalent() {
+        let quine = [109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99];
+        assert_equivalent(&quine, &[]);
+        let cmp8 = [3,9,8,9,10,9,4,9,99,-1,8];
+        assert_equivalent(&cmp8, &[8]);
+        assert_equivalent(&cmp8, &[7]);
+        assert_equivalent(&[1102,34915192,34915192,7,4,7,99,0], &[]);
+        // Faults, including running out of input
+        assert_equivalent(&[1,0,0,0,42], &[]);
+        assert_equivalent(&[3,0,99], &[]);
+        assert_equivalent(&[1105,1,-4], &[]);
+        assert_equivalent(&[2,-1,0,0,99], &[]);
+        assert_equivalent(&[1102,0x7fff_ffff_ffff_ffff,2,0,99], &[]);
+        assert_equivalent(&[10001,0,0,0,99], &[]);
+    }
+
+    #[test]
+    fn test_wide() {
+        use num_bigint::BigInt;
+        use super::super::WideMachine;
+        // Squares 2^40 twice over, then a relative base too big for an
+        // Int, which the cache hands back to the general path.
+        let square = [1102,1<<40,1<<40,13, 2,13,13,13, 4,13, 9,13, 99, 0];
+        let big_base = [109,1<<62, 109,1<<62, 109,1<<62, 204,0, 99];
+        for program in [&square[..], &big_base[..]] {
+            let run = |cached: bool| {
+                let mut machine: WideMachine<BigInt> = IntcodeMachine::wide(program);
+                machine.set_decode_cache(cached);
+                let result = machine.run_until_halt();
+                (result, machine.take_outputs(), machine.rel_base(), machine.get_data(0, 14))
+            };
+            assert_eq!(run(true), run(false));
+            if program == square {
+                assert_eq!(run(true).1, vec![BigInt::from(1) << 160]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_self_modifying() {
+        // The loop rewrites its own add's first argument each time round,
+        // adding 1, then 2, then 3 to the total.
+        let program = assemble(r#"
+            loop:   add  total, #1, total
+                    add  loop + 2, #1, loop + 2
+                    add  count, #-1, count
+                    jt   count, #loop
+                    out  total
+                    hlt
+            count:  data 3
+            total:  data 0
+        "#).unwrap();
+        assert_equivalent(&program, &[]);
+
+        let mut machine = IntcodeMachine::new(&program);
+        machine.set_decode_cache(true);
+        machine.run_until_halt().unwrap();
+        assert_eq!(machine.get_outputs(), &[6]);
+        assert!(!machine.decode_cache().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_external_write() {
+        let mut machine = IntcodeMachine::new(&[1101,1,2,11, 4,11, 1105,1,0, 99, 0, 0]);
+        machine.set_decode_cache(true);
+        assert_eq!(machine.run_until_output().unwrap(), Some(3));
+        // Change the add's argument behind the machine's back
+        machine.set(1, 40).unwrap();
+        assert_eq!(machine.run_until_output().unwrap(), Some(42));
+    }
+}
@@ -0,0 +1,860 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use super::Int;
+use super::asm::assemble;
+
+// A small language which compiles (through the assembler) to Intcode:
+//
+//   var count = 0;                  // globals, initialised to constants
+//
+//   fn fact(n) {
+//       if n < 2 { return 1; }
+//       return n * fact(n - 1);
+//   }
+//
+//   fn main() {
+//       var n = input();
+//       while n > 0 {
+//           output(fact(n));
+//           count = count + 1;
+//           n = n - 1;
+//       }
+//   }
+//
+// Values are integers.  Operators are `||`, `&&` (both short-circuit),
+// `==`, `!=`, `<`, `<=`, `>`, `>=`, `+`, `-`, `*` and unary `-` and `!`,
+// with comparisons and `!` giving 0 or 1.  Variables declared in a
+// function belong to the whole function, and can shadow globals.
+// `input()` and `output(x)` are built in; a function without a `return`
+// returns 0.  The program runs `main` and then halts.
+//
+// Functions use the relative base as a stack pointer.  A frame holds
+// the return address at @0, then the parameters, locals and temporaries.
+// A caller puts the return address and arguments just past its own
+// frame, moves the base there for the call and back afterwards.  Return
+// values are passed in a fixed location.
+
+#[derive(Debug)]
+pub struct CompileError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::error::Error for CompileError {
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+type Result<T> = std::result::Result<T, CompileError>;
+
+fn error<T>(line: usize, message: impl Into<String>) -> Result<T> {
+    Err(CompileError { line, message: message.into() })
+}
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+enum Tok {
+    Num(Int),
+    Ident(String),
+    Sym(&'static str),
+    Eof,
+}
+
+const SYMBOLS: [&str; 21] = [
+    "==", "!=", "<=", ">=", "&&", "||",
+    "(", ")", "{", "}", ",", ";", "=", "<", ">", "+", "-", "*", "!", "/", "%",
+];
+
+fn tokenize(source: &str) -> Result<Vec<(Tok, usize)>> {
+    let mut result = Vec::new();
+    for (lineno, line) in source.lines().enumerate() {
+        let line_no = lineno + 1;
+        let mut rest = line.split("//").next().unwrap_or("").trim_start();
+        while !rest.is_empty() {
+            let c = rest.chars().next().unwrap();
+            let len = if c.is_ascii_digit() {
+                let len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+                match rest[..len].parse() {
+                    Ok(n) => result.push((Tok::Num(n), line_no)),
+                    Err(_) => return error(line_no, format!("Number {} too large", &rest[..len])),
+                }
+                len
+            } else if c.is_ascii_alphabetic() || c == '_' {
+                let len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+                result.push((Tok::Ident(rest[..len].to_string()), line_no));
+                len
+            } else if let Some(sym) = SYMBOLS.iter().find(|s| rest.starts_with(*s)) {
+                result.push((Tok::Sym(sym), line_no));
+                sym.len()
+            } else {
+                return error(line_no, format!("Unexpected character {:?}", c));
+            };
+            rest = rest[len..].trim_start();
+        }
+    }
+    let last = source.lines().count().max(1);
+    result.push((Tok::Eof, last));
+    Ok(result)
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+enum BinOp {
+    Or, And, Eq, Ne, Lt, Le, Gt, Ge, Add, Sub, Mul,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+enum UnOp {
+    Neg, Not,
+}
+
+#[derive(Debug,Clone)]
+enum Expr {
+    Num(Int),
+    Var(String, usize),
+    Call(String, Vec<Expr>, usize),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug,Clone)]
+enum Stmt {
+    Var(String, Option<Expr>, usize),
+    Assign(String, Expr, usize),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Return(Option<Expr>),
+    Expr(Expr),
+}
+
+// Name, initial value and line
+type Global = (String, Int, usize);
+
+#[derive(Debug)]
+struct Function {
+    name: String,
+    params: Vec<String>,
+    body: Vec<Stmt>,
+    line: usize,
+}
+
+struct Parser {
+    tokens: Vec<(Tok, usize)>,
+    pos: usize,
+}
+
+// Binary operators by precedence, loosest first.
+const PRECEDENCE: [&[(&str, BinOp)]; 5] = [
+    &[("||", BinOp::Or)],
+    &[("&&", BinOp::And)],
+    &[("==", BinOp::Eq), ("!=", BinOp::Ne)],
+    &[("<", BinOp::Lt), ("<=", BinOp::Le), (">", BinOp::Gt), (">=", BinOp::Ge)],
+    &[("+", BinOp::Add), ("-", BinOp::Sub)],
+];
+
+impl Parser {
+    fn peek(&self) -> &Tok {
+        &self.tokens[self.pos].0
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.pos].1
+    }
+
+    fn next(&mut self) -> Tok {
+        let tok = self.tokens[self.pos].0.clone();
+        if tok != Tok::Eof {
+            self.pos += 1;
+        }
+        tok
+    }
+
+    fn accept(&mut self, sym: &str) -> bool {
+        if matches!(self.peek(), Tok::Sym(s) if *s == sym) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn accept_keyword(&mut self, word: &str) -> bool {
+        if matches!(self.peek(), Tok::Ident(s) if s == word) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, sym: &str) -> Result<()> {
+        if self.accept(sym) {
+            Ok(())
+        } else {
+            error(self.line(), format!("Expected {:?}, found {}", sym, self.describe()))
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.peek() {
+            Tok::Num(n) => n.to_string(),
+            Tok::Ident(s) => s.clone(),
+            Tok::Sym(s) => format!("{:?}", s),
+            Tok::Eof => "end of input".into(),
+        }
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        match self.peek().clone() {
+            Tok::Ident(s) if !["fn", "var", "if", "else", "while", "return"].contains(&&s[..]) => {
+                self.pos += 1;
+                Ok(s)
+            }
+            _ => error(self.line(), format!("Expected a name, found {}", self.describe())),
+        }
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>> {
+        self.expect("{")?;
+        let mut result = Vec::new();
+        while !self.accept("}") {
+            result.push(self.statement()?);
+        }
+        Ok(result)
+    }
+
+    fn statement(&mut self) -> Result<Stmt> {
+        let line = self.line();
+        if self.accept_keyword("var") {
+            let name = self.ident()?;
+            let init = if self.accept("=") { Some(self.expr()?) } else { None };
+            self.expect(";")?;
+            return Ok(Stmt::Var(name, init, line));
+        }
+        if self.accept_keyword("if") {
+            let cond = self.expr()?;
+            let then = self.block()?;
+            let other = if !self.accept_keyword("else") {
+                Vec::new()
+            } else if matches!(self.peek(), Tok::Ident(s) if s == "if") {
+                vec![self.statement()?]
+            } else {
+                self.block()?
+            };
+            return Ok(Stmt::If(cond, then, other));
+        }
+        if self.accept_keyword("while") {
+            let cond = self.expr()?;
+            return Ok(Stmt::While(cond, self.block()?));
+        }
+        if self.accept_keyword("return") {
+            if self.accept(";") {
+                return Ok(Stmt::Return(None));
+            }
+            let value = self.expr()?;
+            self.expect(";")?;
+            return Ok(Stmt::Return(Some(value)));
+        }
+        if let (Tok::Ident(name), Some((Tok::Sym("="), _))) = (self.peek().clone(), self.tokens.get(self.pos + 1)) {
+            self.pos += 2;
+            let value = self.expr()?;
+            self.expect(";")?;
+            return Ok(Stmt::Assign(name, value, line));
+        }
+        let expr = self.expr()?;
+        self.expect(";")?;
+        Ok(Stmt::Expr(expr))
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr> {
+        if level == PRECEDENCE.len() {
+            return self.product();
+        }
+        let mut left = self.binary(level + 1)?;
+        'outer: loop {
+            for &(sym, op) in PRECEDENCE[level] {
+                if self.accept(sym) {
+                    let right = self.binary(level + 1)?;
+                    left = Expr::Binary(op, Box::new(left), Box::new(right));
+                    continue 'outer;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr> {
+        let mut left = self.unary()?;
+        loop {
+            if self.accept("*") {
+                left = Expr::Binary(BinOp::Mul, Box::new(left), Box::new(self.unary()?));
+            } else if let Tok::Sym(op @ ("/" | "%")) = self.peek() {
+                return error(self.line(), format!("Operator {} isn't supported", op));
+            } else {
+                return Ok(left);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.accept("-") {
+            return Ok(Expr::Unary(UnOp::Neg, Box::new(self.unary()?)));
+        }
+        if self.accept("!") {
+            return Ok(Expr::Unary(UnOp::Not, Box::new(self.unary()?)));
+        }
+        let line = self.line();
+        match self.peek().clone() {
+            Tok::Num(n) => {
+                self.pos += 1;
+                Ok(Expr::Num(n))
+            }
+            Tok::Sym("(") => {
+                self.pos += 1;
+                let expr = self.expr()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            _ => {
+                let name = self.ident()?;
+                if !self.accept("(") {
+                    return Ok(Expr::Var(name, line));
+                }
+                let mut args = Vec::new();
+                if !self.accept(")") {
+                    loop {
+                        args.push(self.expr()?);
+                        if self.accept(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                Ok(Expr::Call(name, args, line))
+            }
+        }
+    }
+
+    // Returns the globals (with initial values) and functions.
+    fn program(&mut self) -> Result<(Vec<Global>, Vec<Function>)> {
+        let mut globals = Vec::new();
+        let mut functions = Vec::new();
+        loop {
+            let line = self.line();
+            if self.accept_keyword("var") {
+                let name = self.ident()?;
+                let init = if self.accept("=") {
+                    let negative = self.accept("-");
+                    match self.next() {
+                        Tok::Num(n) if negative => -n,
+                        Tok::Num(n) => n,
+                        _ => return error(line, "Globals can only be initialised to a number"),
+                    }
+                } else {
+                    0
+                };
+                self.expect(";")?;
+                globals.push((name, init, line));
+            } else if self.accept_keyword("fn") {
+                let name = self.ident()?;
+                self.expect("(")?;
+                let mut params = Vec::new();
+                if !self.accept(")") {
+                    loop {
+                        params.push(self.ident()?);
+                        if self.accept(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                let body = self.block()?;
+                functions.push(Function { name, params, body, line });
+            } else if *self.peek() == Tok::Eof {
+                return Ok((globals, functions));
+            } else {
+                return error(line, format!("Expected fn or var, found {}", self.describe()));
+            }
+        }
+    }
+}
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+enum Operand {
+    Imm(Int),
+    Global(String),
+    Slot(Int),
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Operand::Imm(n) => write!(f, "#{}", n),
+            Operand::Global(name) => write!(f, "g_{}", name),
+            Operand::Slot(n) => write!(f, "@{}", n),
+        }
+    }
+}
+
+// Stands for the frame size until the whole function has been compiled.
+const FRAME: &str = "{frame}";
+
+fn count_vars(body: &[Stmt]) -> usize {
+    body.iter().map(|stmt| match stmt {
+        Stmt::Var(..) => 1,
+        Stmt::If(_, then, other) => count_vars(then) + count_vars(other),
+        Stmt::While(_, body) => count_vars(body),
+        _ => 0,
+    }).sum()
+}
+
+// Calls can change globals, so a value already worked out has to be
+// copied before evaluating anything with a call in it.
+fn contains_call(expr: &Expr) -> bool {
+    match expr {
+        Expr::Num(_) | Expr::Var(..) => false,
+        Expr::Call(..) => true,
+        Expr::Unary(_, arg) => contains_call(arg),
+        Expr::Binary(_, left, right) => contains_call(left) || contains_call(right),
+    }
+}
+
+fn fold(op: BinOp, a: Int, b: Int) -> Int {
+    match op {
+        BinOp::Or => (a != 0 || b != 0) as Int,
+        BinOp::And => (a != 0 && b != 0) as Int,
+        BinOp::Eq => (a == b) as Int,
+        BinOp::Ne => (a != b) as Int,
+        BinOp::Lt => (a < b) as Int,
+        BinOp::Le => (a <= b) as Int,
+        BinOp::Gt => (a > b) as Int,
+        BinOp::Ge => (a >= b) as Int,
+        BinOp::Add => a.wrapping_add(b),
+        BinOp::Sub => a.wrapping_sub(b),
+        BinOp::Mul => a.wrapping_mul(b),
+    }
+}
+
+struct Compiler<'a> {
+    globals: &'a HashSet<String>,
+    // Number of parameters of each function
+    functions: &'a HashMap<String, usize>,
+    vars: HashMap<String, Int>,
+    next_var: Int,
+    // Temporaries start after every variable the function declares.
+    temp_base: Int,
+    temps: Int,
+    max_temps: Int,
+    code: Vec<String>,
+    labels: &'a mut usize,
+}
+
+impl Compiler<'_> {
+    fn emit(&mut self, line: String) {
+        self.code.push(format!("        {}", line));
+    }
+
+    fn label(&mut self) -> String {
+        *self.labels += 1;
+        format!("_L{}", self.labels)
+    }
+
+    fn place(&mut self, label: &str) {
+        self.code.push(format!("{}:", label));
+    }
+
+    fn temp(&mut self) -> Operand {
+        let slot = self.temp_base + self.temps;
+        self.temps += 1;
+        self.max_temps = self.max_temps.max(self.temps);
+        Operand::Slot(slot)
+    }
+
+    // The operand, copied to a temporary unless it's a constant.
+    fn copy(&mut self, a: Operand) -> Operand {
+        if let Operand::Imm(_) = a {
+            return a;
+        }
+        let t = self.temp();
+        self.emit(format!("add  {}, #0, {}", a, t));
+        t
+    }
+
+    fn var(&self, name: &str, line: usize) -> Result<Operand> {
+        if let Some(&slot) = self.vars.get(name) {
+            Ok(Operand::Slot(slot))
+        } else if self.globals.contains(name) {
+            Ok(Operand::Global(name.into()))
+        } else {
+            error(line, format!("Unknown variable {}", name))
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<Operand> {
+        match expr {
+            Expr::Num(n) => Ok(Operand::Imm(*n)),
+            Expr::Var(name, line) => self.var(name, *line),
+            Expr::Call(name, args, line) => self.call(name, args, *line),
+            Expr::Unary(op, arg) => {
+                let a = self.expr(arg)?;
+                if let Operand::Imm(n) = a {
+                    return Ok(Operand::Imm(if *op == UnOp::Neg { n.wrapping_neg() } else { (n == 0) as Int }));
+                }
+                let t = self.temp();
+                match op {
+                    UnOp::Neg => self.emit(format!("mul  {}, #-1, {}", a, t)),
+                    UnOp::Not => self.emit(format!("eq   {}, #0, {}", a, t)),
+                }
+                Ok(t)
+            }
+            Expr::Binary(op @ (BinOp::And | BinOp::Or), left, right) => {
+                let a = self.expr(left)?;
+                let t = self.temp();
+                let end = self.label();
+                let (initial, jump) = if *op == BinOp::And { (0, "jf") } else { (1, "jt") };
+                self.emit(format!("add  #{}, #0, {}", initial, t));
+                self.emit(format!("{}   {}, #{}", jump, a, end));
+                let b = self.expr(right)?;
+                self.emit(format!("eq   {}, #0, {}", b, t));
+                self.emit(format!("eq   {}, #0, {}", t, t));
+                self.place(&end);
+                Ok(t)
+            }
+            Expr::Binary(op, left, right) => {
+                let mut a = self.expr(left)?;
+                if contains_call(right) {
+                    a = self.copy(a);
+                }
+                let b = self.expr(right)?;
+                if let (Operand::Imm(x), Operand::Imm(y)) = (&a, &b) {
+                    return Ok(Operand::Imm(fold(*op, *x, *y)));
+                }
+                let t = self.temp();
+                let (insn, a, b, negate) = match op {
+                    BinOp::Add => ("add", a, b, false),
+                    BinOp::Sub => {
+                        let b = match b {
+                            Operand::Imm(n) => Operand::Imm(n.wrapping_neg()),
+                            b => {
+                                self.emit(format!("mul  {}, #-1, {}", b, t));
+                                t.clone()
+                            }
+                        };
+                        ("add", a, b, false)
+                    }
+                    BinOp::Mul => ("mul", a, b, false),
+                    BinOp::Eq => ("eq", a, b, false),
+                    BinOp::Ne => ("eq", a, b, true),
+                    BinOp::Lt => ("lt", a, b, false),
+                    BinOp::Gt => ("lt", b, a, false),
+                    BinOp::Le => ("lt", b, a, true),
+                    BinOp::Ge => ("lt", a, b, true),
+                    BinOp::And | BinOp::Or => unreachable!(),
+                };
+                self.emit(format!("{:<4} {}, {}, {}", insn, a, b, t));
+                if negate {
+                    self.emit(format!("eq   {}, #0, {}", t, t));
+                }
+                Ok(t)
+            }
+        }
+    }
+
+    fn call(&mut self, name: &str, args: &[Expr], line: usize) -> Result<Operand> {
+        let builtin = match name {
+            "input" => Some(0),
+            "output" => Some(1),
+            _ => None,
+        };
+        let params = match builtin.or_else(|| self.functions.get(name).cloned()) {
+            Some(params) => params,
+            None => return error(line, format!("Unknown function {}", name)),
+        };
+        if args.len() != params {
+            return error(line, format!("{} takes {} arguments, not {}", name, params, args.len()));
+        }
+        // Evaluate everything before filling in the new frame, since
+        // calls among the arguments use the same space.
+        let mut values = Vec::new();
+        for (i, arg) in args.iter().enumerate() {
+            let val = self.expr(arg)?;
+            values.push(if args[i + 1..].iter().any(contains_call) { self.copy(val) } else { val });
+        }
+        let args = values;
+        if name == "input" {
+            let t = self.temp();
+            self.emit(format!("in   {}", t));
+            return Ok(t);
+        }
+        if name == "output" {
+            self.emit(format!("out  {}", args[0]));
+            return Ok(Operand::Imm(0));
+        }
+        let ret = self.label();
+        for (i, arg) in args.iter().enumerate() {
+            self.emit(format!("add  {}, #0, @{}+{}", arg, FRAME, i + 1));
+        }
+        self.emit(format!("add  #{}, #0, @{}", ret, FRAME));
+        self.emit(format!("arb  #{}", FRAME));
+        self.emit(format!("jt   #1, #f_{}", name));
+        self.place(&ret);
+        self.emit(format!("arb  #-{}", FRAME));
+        let t = self.temp();
+        self.emit(format!("add  _ret, #0, {}", t));
+        Ok(t)
+    }
+
+    fn statements(&mut self, body: &[Stmt]) -> Result<()> {
+        for stmt in body {
+            self.statement(stmt)?;
+            self.temps = 0;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<()> {
+        match stmt {
+            Stmt::Var(name, init, line) => {
+                if self.vars.contains_key(name) {
+                    return error(*line, format!("Variable {} already declared", name));
+                }
+                // Evaluated before the variable exists
+                let value = init.as_ref().map(|e| self.expr(e)).transpose()?;
+                let slot = self.next_var;
+                self.next_var += 1;
+                self.vars.insert(name.clone(), slot);
+                self.emit(format!("add  {}, #0, @{}", value.unwrap_or(Operand::Imm(0)), slot));
+            }
+            Stmt::Assign(name, value, line) => {
+                let dest = self.var(name, *line)?;
+                let value = self.expr(value)?;
+                self.emit(format!("add  {}, #0, {}", value, dest));
+            }
+            Stmt::If(cond, then, other) => {
+                let else_label = self.label();
+                let end = self.label();
+                let c = self.expr(cond)?;
+                self.emit(format!("jf   {}, #{}", c, else_label));
+                self.temps = 0;
+                self.statements(then)?;
+                if !other.is_empty() {
+                    self.emit(format!("jt   #1, #{}", end));
+                }
+                self.place(&else_label);
+                self.statements(other)?;
+                self.place(&end);
+            }
+            Stmt::While(cond, body) => {
+                let top = self.label();
+                let end = self.label();
+                self.place(&top);
+                let c = self.expr(cond)?;
+                self.emit(format!("jf   {}, #{}", c, end));
+                self.temps = 0;
+                self.statements(body)?;
+                self.emit(format!("jt   #1, #{}", top));
+                self.place(&end);
+            }
+            Stmt::Return(value) => {
+                let value = value.as_ref().map(|e| self.expr(e)).transpose()?;
+                self.emit(format!("add  {}, #0, _ret", value.unwrap_or(Operand::Imm(0))));
+                self.emit("jt   #1, @0".into());
+            }
+            Stmt::Expr(expr) => {
+                self.expr(expr)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Compile to assembler source for `asm::assemble`.
+pub fn compile_to_asm(source: &str) -> Result<String> {
+    let mut parser = Parser { tokens: tokenize(source)?, pos: 0 };
+    let (globals, functions) = parser.program()?;
+
+    let mut global_names = HashSet::new();
+    for (name, _, line) in &globals {
+        if !global_names.insert(name.clone()) {
+            return error(*line, format!("Global {} already declared", name));
+        }
+    }
+    let mut arities = HashMap::new();
+    for function in &functions {
+        if ["input", "output"].contains(&&function.name[..])
+            || arities.insert(function.name.clone(), function.params.len()).is_some() {
+            return error(function.line, format!("Function {} already defined", function.name));
+        }
+    }
+    match arities.get("main") {
+        Some(0) => {}
+        Some(_) => return error(1, "main can't take arguments"),
+        None => return error(1, "No main function"),
+    }
+
+    let mut result = String::new();
+    result += "        arb  #_stack\n";
+    result += "        add  #_exit, #0, @0\n";
+    result += "        jt   #1, #f_main\n";
+    result += "_exit:  hlt\n";
+    let mut labels = 0;
+    for function in &functions {
+        let mut compiler = Compiler {
+            globals: &global_names,
+            functions: &arities,
+            vars: HashMap::new(),
+            next_var: 1,
+            temp_base: 0,
+            temps: 0,
+            max_temps: 0,
+            code: Vec::new(),
+            labels: &mut labels,
+        };
+        for param in &function.params {
+            if compiler.vars.insert(param.clone(), compiler.next_var).is_some() {
+                return error(function.line, format!("Parameter {} repeated", param));
+            }
+            compiler.next_var += 1;
+        }
+        compiler.temp_base = compiler.next_var + count_vars(&function.body) as Int;
+        compiler.statements(&function.body)?;
+        compiler.statement(&Stmt::Return(None))?;
+
+        let frame = (compiler.temp_base + compiler.max_temps).to_string();
+        result += &format!("f_{}:\n", function.name);
+        for line in &compiler.code {
+            result += &line.replace(FRAME, &frame);
+            result.push('\n');
+        }
+    }
+    result += "_ret:   data 0\n";
+    for (name, init, _) in &globals {
+        result += &format!("g_{}: data {}\n", name, init);
+    }
+    result += "_stack: data 0\n";
+    Ok(result)
+}
+
+pub fn compile(source: &str) -> Result<Vec<Int>> {
+    let asm = compile_to_asm(source)?;
+    assemble(&asm).map_err(|e| CompileError { line: 0, message: format!("Assembler: {}", e) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::IntcodeMachine;
+
+    fn run(source: &str, input: &[Int]) -> Vec<Int> {
+        let mut machine = IntcodeMachine::new(&compile(source).unwrap());
+        for &val in input {
+            machine.send_input(val);
+        }
+        machine.run_until_halt().unwrap();
+        machine.take_outputs()
+    }
+
+    #[test]
+    fn test_loops() {
+        let source = r#"
+            var count = 0;
+
+            fn main() {
+                var n = input();
+                var total = 0;
+                while n > 0 {
+                    total = total + n;
+                    n = n - 1;
+                    count = count + 1;
+                }
+                output(total);
+                output(count);
+                if total >= 10 && count != 3 {
+                    output(1);
+                } else if !(total < 6) || 0 {
+                    output(2);
+                } else {
+                    output(3);
+                }
+                output(-total * 2 - -1 <= 7 - 20);
+            }
+        "#;
+        assert_eq!(run(source, &[4]), vec![10, 4, 1, 1]);
+        assert_eq!(run(source, &[3]), vec![6, 3, 2, 0]);
+        assert_eq!(run(source, &[0]), vec![0, 0, 3, 0]);
+    }
+
+    #[test]
+    fn test_functions() {
+        let source = r#"
+            fn fact(n) {
+                if n < 2 { return 1; }
+                return n * fact(n - 1);
+            }
+
+            fn fib(n) {
+                if n < 2 { return n; }
+                var a = fib(n - 1);
+                return a + fib(n - 2);
+            }
+
+            fn sub(a, b) { return a - b; }
+
+            // Arguments are evaluated left to right, calls and all.
+            fn noisy(x) {
+                output(x);
+                return x;
+            }
+
+            fn main() {
+                output(fact(input()));
+                output(fib(10));
+                output(sub(fact(4), sub(noisy(7), noisy(2))));
+                output(1 || noisy(99));
+            }
+        "#;
+        assert_eq!(run(source, &[5]), vec![120, 55, 7, 2, 19, 1]);
+    }
+
+    #[test]
+    fn test_globals_and_calls() {
+        // Globals are read before calls to their right change them.
+        let source = r#"
+            var g = 1;
+
+            fn f() {
+                g = 10;
+                return 0;
+            }
+
+            fn sub(a, b) { return a - b; }
+
+            fn main() {
+                output(g + f());
+                output(g + f());
+                g = 5;
+                output(sub(g, f()));
+                g = 3;
+                output(g * (f() + 1));
+            }
+        "#;
+        assert_eq!(run(source, &[]), vec![1, 10, 5, 3]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |source: &str| compile(source).unwrap_err().to_string();
+        assert_eq!(err("fn main() {\n  x = 1;\n}"), "line 2: Unknown variable x");
+        assert_eq!(err("fn f(a) {}\nfn main() { f(); }"), "line 2: f takes 1 arguments, not 0");
+        assert_eq!(err("fn f() {}"), "line 1: No main function");
+        assert_eq!(err("fn main() {\n  var a = 1 / 2;\n}"), "line 2: Operator / isn't supported");
+        assert_eq!(err("fn main() {\n  var a;\n  var a;\n}"), "line 3: Variable a already declared");
+        assert_eq!(err("fn main() {\n  output(1)\n}"), "line 3: Expected \";\", found \"}\"");
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use super::{Int, IntcodeMachine, Word};
+use super::disasm::{Line, decode_line};
+use super::memory::Words;
+use super::trace::{Tracer, TraceEntry};
+
+// Coverage: which instructions were executed, and which ways the
+// branches went.  For jumps (`jt`, `jf`) that's whether the jump was
+// taken; for comparisons (`lt`, `eq`) whether they held.  It's a tracer,
+// so it can be added to any number of machines (or runs) and gathers
+// everything they execute.
+//
+// Reports list the program with the counts alongside.  The listing is
+// of the memory given, normally the program as loaded, so code which a
+// program writes for itself shows as it was before.  Of a machine's
+// memory only what has been written is listed.
+
+#[derive(Debug,Clone,Copy,Default,PartialEq,Eq)]
+pub struct Branch {
+    // For comparisons, times the comparison held and didn't
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+impl Branch {
+    // Number of directions (0 to 2) exercised
+    pub fn directions(&self) -> usize {
+        (self.taken > 0) as usize + (self.not_taken > 0) as usize
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Summary {
+    pub instructions: usize,
+    pub instructions_covered: usize,
+    // Two per jump or comparison
+    pub directions: usize,
+    pub directions_covered: usize,
+}
+
+fn percent(part: usize, whole: usize) -> f64 {
+    if whole == 0 { 100.0 } else { part as f64 * 100.0 / whole as f64 }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "Instructions {}/{} ({:.1}%), branch directions {}/{} ({:.1}%)",
+               self.instructions_covered, self.instructions,
+               percent(self.instructions_covered, self.instructions),
+               self.directions_covered, self.directions,
+               percent(self.directions_covered, self.directions))
+    }
+}
+
+fn is_branch(line: &Line) -> bool {
+    matches!(line, Line::Insn { insn, .. } if matches!(insn.op.opcode, 5..=8))
+}
+
+#[derive(Debug,Clone,Default)]
+pub struct Coverage {
+    hits: HashMap<usize, u64>,
+    branches: HashMap<usize, Branch>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Default::default()
+    }
+
+    pub fn hits(&self, addr: usize) -> u64 {
+        self.hits.get(&addr).cloned().unwrap_or(0)
+    }
+
+    pub fn branch(&self, addr: usize) -> Option<Branch> {
+        self.branches.get(&addr).cloned()
+    }
+
+    // The addresses executed, in order.
+    pub fn covered(&self) -> Vec<usize> {
+        let mut result: Vec<_> = self.hits.keys().cloned().collect();
+        result.sort_unstable();
+        result
+    }
+
+    pub fn merge(&mut self, other: &Coverage) {
+        for (&addr, &hits) in &other.hits {
+            *self.hits.entry(addr).or_insert(0) += hits;
+        }
+        for (&addr, branch) in &other.branches {
+            let entry = self.branches.entry(addr).or_default();
+            entry.taken += branch.taken;
+            entry.not_taken += branch.not_taken;
+        }
+    }
+
+    // The program from `start` as instructions and data, like the
+    // disassembler's, except that an instruction which would cover an
+    // executed address is left as data so that the listing lines up
+    // with what was run.
+    pub fn lines<M: Words + ?Sized>(&self, mem: &M, start: usize) -> Vec<Line> {
+        let mut result = Vec::new();
+        let mut addr = start;
+        for (low, end) in mem.loaded() {
+            addr = addr.max(low);
+            while addr < end {
+                let mut line = decode_line(mem, addr);
+                if (addr + 1..addr + line.size()).any(|a| self.hits.contains_key(&a)) {
+                    line = Line::Data { addr, value: mem.word(addr).unwrap_or(0) };
+                }
+                addr += line.size();
+                result.push(line);
+            }
+        }
+        result
+    }
+
+    pub fn summary<M: Words + ?Sized>(&self, mem: &M, start: usize) -> Summary {
+        let mut summary = Summary { instructions: 0, instructions_covered: 0, directions: 0, directions_covered: 0 };
+        for line in self.lines(mem, start) {
+            if let Line::Data { .. } = line {
+                continue;
+            }
+            summary.instructions += 1;
+            summary.instructions_covered += self.hits.contains_key(&line.addr()) as usize;
+            if is_branch(&line) {
+                summary.directions += 2;
+                summary.directions_covered += self.branch(line.addr()).map_or(0, |b| b.directions());
+            }
+        }
+        summary
+    }
+
+    // The summary, then a listing from `start` with each instruction's
+    // execution count (##### if never) and the directions of branches.
+    pub fn report<M: Words + ?Sized>(&self, mem: &M, start: usize) -> String {
+        let mut result = format!("{}\n\n", self.summary(mem, start));
+        for line in self.lines(mem, start) {
+            let addr = line.addr();
+            let count = match (&line, self.hits(addr)) {
+                (Line::Data { .. }, _) => String::new(),
+                (_, 0) => "#####".into(),
+                (_, hits) => hits.to_string(),
+            };
+            let mut text = format!("{:>8}  {:6}  {:<28}", count, addr, line.to_string());
+            if let (true, Some(branch)) = (is_branch(&line), self.branch(addr)) {
+                let (yes, no) = match &line {
+                    Line::Insn { insn, .. } if insn.op.opcode >= 7 => ("true", "false"),
+                    _ => ("taken", "not taken"),
+                };
+                text += &format!("; {} {}, {} {}", yes, branch.taken, no, branch.not_taken);
+            }
+            result += text.trim_end();
+            result.push('\n');
+        }
+        result
+    }
+}
+
+impl<W: Word> Tracer<W> for Coverage {
+    fn trace(&mut self, entry: &TraceEntry<W>) {
+        *self.hits.entry(entry.pc).or_insert(0) += 1;
+        let opcode = match &entry.line {
+            Line::Insn { insn, .. } => insn.op.opcode,
+            Line::Data { .. } => return,
+        };
+        let held = match (opcode, &entry.reads[..]) {
+            (5, [cond, _]) => !cond.is_zero(),
+            (6, [cond, _]) => cond.is_zero(),
+            (7, [a, b]) => a < b,
+            (8, [a, b]) => a == b,
+            _ => return,
+        };
+        let branch = self.branches.entry(entry.pc).or_default();
+        if held {
+            branch.taken += 1;
+        } else {
+            branch.not_taken += 1;
+        }
+    }
+}
+
+// Runs the program once for each set of inputs and gathers the coverage
+// of them all.  Runs which fault (or hit the step limit) count as far as
+// they got.
+pub fn cover(program: &[Int], scenarios: &[Vec<Int>], step_limit: Option<u64>) -> Coverage {
+    let coverage = Arc::new(Mutex::new(Coverage::new()));
+    for inputs in scenarios {
+        let mut machine = IntcodeMachine::new(program);
+        machine.set_step_limit(step_limit);
+        machine.add_tracer(coverage.clone());
+        for &val in inputs {
+            machine.send_input(val);
+        }
+        let _ = machine.run_until_halt();
+    }
+    let result = coverage.lock().unwrap().clone();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::asm::assemble;
+
+    // Outputs 0 for x < 10, 2 for x = 99 and otherwise 1.
+    const CLASSIFY: &str = r#"
+                in   x
+                lt   x, #10, t
+                jf   t, #large
+                out  #0
+                hlt
+        large:  eq   x, #99, t
+                jt   t, #odd
+                out  #1
+                hlt
+        odd:    out  #2
+                hlt
+        x:      data 0
+        t:      data 0
+    "#;
+
+    #[test]
+    fn test_coverage() {
+        let program = assemble(CLASSIFY).unwrap();
+        let coverage = cover(&program, &[vec![5], vec![50]], None);
+        assert_eq!(coverage.hits(0), 2);
+        assert_eq!(coverage.hits(9), 1);
+        assert_eq!(coverage.hits(22), 0);
+        assert_eq!(coverage.branch(2), Some(Branch { taken: 1, not_taken: 1 }));
+        assert_eq!(coverage.branch(6), Some(Branch { taken: 1, not_taken: 1 }));
+        assert_eq!(coverage.branch(16), Some(Branch { taken: 0, not_taken: 1 }));
+        assert_eq!(coverage.branch(22), None);
+        assert_eq!(coverage.summary(&program, 0).to_string(),
+                   "Instructions 9/11 (81.8%), branch directions 6/8 (75.0%)");
+
+        let report = coverage.report(&program, 0);
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines[3], "       2       2  lt  25, #10, 26             ; true 1, false 1");
+        assert_eq!(lines[8], "       1      16  jt  26, #22                 ; taken 0, not taken 1");
+        assert_eq!(lines[11], "   #####      22  out #2");
+        assert_eq!(lines[13], "              25  data 0");
+
+        // Adding the other case covers everything.
+        let mut all = coverage.clone();
+        all.merge(&cover(&program, &[vec![99]], None));
+        assert_eq!(all.hits(0), 3);
+        let summary = all.summary(&program, 0);
+        assert_eq!((summary.instructions_covered, summary.directions_covered), (11, 8));
+    }
+
+    #[test]
+    fn test_alignment() {
+        // Jumps into what looks like an add at 3, to output 7 from 4.
+        let program = [1105, 1, 4, 1101, 104, 7, 99];
+        let coverage = cover(&program, &[vec![]], Some(10));
+        assert_eq!(coverage.covered(), vec![0, 4, 6]);
+        let lines: Vec<_> = coverage.lines(&program[..], 0).iter().map(|l| l.to_string()).collect();
+        assert_eq!(lines, ["jt  #1, #4", "data 1101", "out #7", "hlt"]);
+        assert_eq!(coverage.summary(&program[..], 0).instructions, 3);
+    }
+
+    #[test]
+    fn test_high_write() {
+        // Only the program and the page written far beyond it are listed.
+        let mut machine = IntcodeMachine::new(&[1101, 0, 99, 1 << 40, 99]);
+        let coverage = Arc::new(Mutex::new(Coverage::new()));
+        machine.add_tracer(coverage.clone());
+        machine.run_until_halt().unwrap();
+        let lines = coverage.lock().unwrap().lines(machine.memory(), 0);
+        let addrs: Vec<_> = lines.iter().map(|l| l.addr()).collect();
+        assert_eq!(addrs, vec![0, 4, 1 << 40]);
+    }
+}
@@ -0,0 +1,241 @@
+use std::sync::Arc;
+use super::{Int, IntcodeMachine, Input, Output, Error, Word, op_info, jump_target};
+
+// Extra opcodes, for trying out extensions to the instruction set or
+// emulating variant machines.  Each has a number of parameters, decoded
+// with the usual modes, and a handler which does the work through a
+// `Call`.  Unless the handler jumps or halts, execution continues after
+// the parameters.  The built in opcodes can't be replaced.
+//
+// Only the interpreter knows about these: the decode cache leaves them to
+// it, the disassembler shows them as data, and watchpoints don't see the
+// accesses they make.
+
+pub type Handler<I, O, W> = Arc<dyn Fn(&mut Call<I, O, W>) -> Result<(), Error<W>> + Send + Sync>;
+
+pub struct CustomOp<I, O, W> {
+    pub mnemonic: String,
+    pub params: usize,
+    handler: Handler<I, O, W>,
+}
+
+impl<I, O, W> Clone for CustomOp<I, O, W> {
+    fn clone(&self) -> Self {
+        CustomOp {
+            mnemonic: self.mnemonic.clone(),
+            params: self.params,
+            handler: self.handler.clone(),
+        }
+    }
+}
+
+// The machine as seen by a handler while its instruction runs.
+pub struct Call<'a, I, O, W> {
+    machine: &'a mut IntcodeMachine<I, O, W>,
+    insn: W,
+    code: Int,
+    params: usize,
+    next_pc: Option<usize>,
+    // Input read, to put back if the instruction fails
+    inputs: Vec<W>,
+}
+
+impl<I: Input<W>, O: Output<W>, W: Word> Call<'_, I, O, W> {
+    pub fn pc(&self) -> usize {
+        self.machine.pc
+    }
+
+    pub fn insn(&self) -> &W {
+        &self.insn
+    }
+
+    pub fn params(&self) -> usize {
+        self.params
+    }
+
+    // The mode digit of parameter n.
+    pub fn mode(&self, n: usize) -> Int {
+        let mut code = self.code / 100;
+        for _ in 0..n {
+            code /= 10;
+        }
+        code % 10
+    }
+
+    fn check_param(&self, n: usize) -> Result<(), Error<W>> {
+        if n >= self.params {
+            return Err(Error::InvalidMode { pc: self.pc(), insn: self.insn.clone() });
+        }
+        Ok(())
+    }
+
+    // The value of parameter n.
+    pub fn read(&self, n: usize) -> Result<W, Error<W>> {
+        self.check_param(n)?;
+        self.machine.read_param(n, self.mode(n), &self.insn)
+    }
+
+    // The address parameter n refers to.
+    pub fn addr(&self, n: usize) -> Result<usize, Error<W>> {
+        self.check_param(n)?;
+        self.machine.param_addr(n, self.mode(n), &self.insn)
+    }
+
+    pub fn write(&mut self, n: usize, val: W) -> Result<(), Error<W>> {
+        let addr = self.addr(n)?;
+        self.machine.store(addr, val)
+    }
+
+    // If there's no input the instruction fails, and is run again once
+    // there is some.  Any input it had already read is put back, but
+    // anything else it did isn't undone, so read input first.
+    pub fn input(&mut self) -> Result<W, Error<W>> {
+        let val = self.machine.get_input()?;
+        self.inputs.push(val.clone());
+        Ok(val)
+    }
+
+    pub fn output(&mut self, val: W) {
+        self.machine.output(val);
+    }
+
+    pub fn jump(&mut self, target: W) -> Result<(), Error<W>> {
+        self.next_pc = Some(jump_target(self.pc(), &self.insn, target)?);
+        Ok(())
+    }
+
+    pub fn halt(&mut self) {
+        self.machine.halted = true;
+    }
+
+    // For anything else, such as the relative base.  Memory written
+    // through `set` isn't seen by the undo history.
+    pub fn machine(&self) -> &IntcodeMachine<I, O, W> {
+        self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut IntcodeMachine<I, O, W> {
+        self.machine
+    }
+}
+
+impl<I: Input<W>, O: Output<W>, W: Word> IntcodeMachine<I, O, W> {
+    // Add an opcode (1 to 99, not already built in), replacing any custom
+    // one with the same number.  Returns false if the opcode can't be used.
+    pub fn define_opcode<F>(&mut self, opcode: Int, mnemonic: &str, params: usize, handler: F) -> bool
+        where F: Fn(&mut Call<I, O, W>) -> Result<(), Error<W>> + Send + Sync + 'static
+    {
+        if !(1..100).contains(&opcode) || op_info(opcode).is_some() {
+            return false;
+        }
+        let op = CustomOp { mnemonic: mnemonic.into(), params, handler: Arc::new(handler) };
+        self.custom_ops.insert(opcode, op);
+        true
+    }
+
+    pub fn remove_opcode(&mut self, opcode: Int) -> bool {
+        self.custom_ops.remove(&opcode).is_some()
+    }
+
+    pub fn custom_opcode(&self, opcode: Int) -> Option<&CustomOp<I, O, W>> {
+        self.custom_ops.get(&opcode)
+    }
+
+    // Run the custom instruction `code` (the word at pc) if defined.
+    pub(super) fn execute_custom(&mut self, code: Int, insn: W) -> Result<(), Error<W>> {
+        let pc = self.pc;
+        let op = match self.custom_ops.get(&(code % 100)) {
+            Some(op) => op.clone(),
+            None => return Err(Error::UnknownInstruction { pc, insn }),
+        };
+        let next = self.pc_offset(1 + op.params, &insn)?;
+        let mut call = Call { machine: self, insn, code, params: op.params, next_pc: None, inputs: Vec::new() };
+        if let Err(e) = (op.handler)(&mut call) {
+            for val in call.inputs.into_iter().rev() {
+                call.machine.inputs.unread(val);
+            }
+            return Err(e);
+        }
+        let next_pc = call.next_pc;
+        if let Some(target) = next_pc {
+            self.pc = target;
+        } else if !self.halted {
+            self.pc = next;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Event;
+
+    // sub, and "djnz": decrement the first parameter and jump to the
+    // second unless it reaches zero.
+    fn machine(program: &[Int]) -> IntcodeMachine {
+        let mut machine = IntcodeMachine::new(program);
+        assert!(machine.define_opcode(10, "sub", 3, |call| {
+            let val = call.read(0)? - call.read(1)?;
+            call.write(2, val)
+        }));
+        assert!(machine.define_opcode(11, "djnz", 2, |call| {
+            let val = call.read(0)? - 1;
+            call.write(0, val)?;
+            if val != 0 {
+                call.jump(call.read(1)?)?;
+            }
+            Ok(())
+        }));
+        machine
+    }
+
+    #[test]
+    fn test_custom() {
+        // Outputs 10 - 3 = 7, then counts down 3, 2, 1.
+        let program = [10, 13, 14, 15, 4, 15, 4, 14, 1011, 14, 6, 99, 0, 10, 3, 0];
+        let mut m = machine(&program);
+        m.run_until_halt().unwrap();
+        assert_eq!(m.get_outputs(), &[7, 3, 2, 1]);
+        assert_eq!(m.custom_opcode(11).map(|op| op.params), Some(2));
+
+        let mut cached = machine(&program);
+        cached.set_decode_cache(true);
+        cached.enable_history(None);
+        cached.run_until_halt().unwrap();
+        assert_eq!(cached.get_outputs(), &[7, 3, 2, 1]);
+        assert!(cached.rewind_to(0));
+        assert_eq!(cached.get_data(0, program.len()), program);
+
+        assert!(!m.define_opcode(1, "add2", 3, |_| Ok(())));
+        assert!(!m.define_opcode(100, "big", 0, |_| Ok(())));
+        assert!(m.remove_opcode(10));
+        m.set_pc(0);
+        assert_eq!(m.step(), Err(Error::UnknownInstruction { pc: 0, insn: 10 }));
+    }
+
+    #[test]
+    fn test_input_and_halt() {
+        let mut m = IntcodeMachine::new(&[12, 12, 13]);
+        m.define_opcode(12, "in2", 0, |call| {
+            let a = call.input()?;
+            let b = call.input()?;
+            call.output(a + b);
+            Ok(())
+        });
+        m.define_opcode(13, "stop", 0, |call| {
+            call.halt();
+            Ok(())
+        });
+        m.send_input(3);
+        m.send_input(4);
+        assert_eq!(m.run_until_event(), Ok(Event::Output(7)));
+        m.send_input(5);
+        assert_eq!(m.run_until_event(), Ok(Event::InputNeeded));
+        assert_eq!(m.pc(), 1);
+        m.send_input(6);
+        assert_eq!(m.run_until_event(), Ok(Event::Output(11)));
+        assert_eq!(m.run_until_event(), Ok(Event::Halted));
+        assert_eq!(m.pc(), 2);
+    }
+}
@@ -0,0 +1,636 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Display;
+use super::{Int, Mode};
+use super::analysis::{Analysis, Block, Exit, analyse};
+use super::disasm::Line;
+use super::memory::Words;
+
+// Turns a program into pseudocode, using the control flow analysis and
+// some idioms of compiled Intcode:
+//
+// - A call stores the return address (the address just after the jump)
+//   then jumps unconditionally to the function.
+// - A return is an unconditional jump to an address read from memory.
+// - Functions keep their variables in a frame addressed through the
+//   relative base, allocated by `arb` with a constant.  Relative operands
+//   are named by their offset from the base once the frame is at its
+//   largest: `local<n>` at or above it, `param<n>` below.
+//
+// Conditional jumps are turned into `if`/`else` and loops where the code
+// between them is only entered at the top; anything else is left as
+// labels and gotos.  Memory at fixed addresses is shown as `m<addr>`.
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Function {
+    pub entry: usize,
+    pub name: String,
+    // Starts of the blocks making up the function
+    pub blocks: Vec<usize>,
+    // Entries of the functions it calls
+    pub calls: BTreeSet<usize>,
+    // Frame slots used, counting from the relative base on entry, and
+    // the largest frame allocated (if known)
+    pub slots: BTreeSet<Int>,
+    pub frame: Option<Int>,
+}
+
+#[derive(Debug,Clone)]
+pub struct Decompiled {
+    pub analysis: Analysis,
+    pub functions: BTreeMap<usize, Function>,
+    // Blocks ending in a call: block start to (function, return address)
+    calls: HashMap<usize, (usize, usize)>,
+    // Frame offset from the entry's relative base at the start of each
+    // block, where known, per function.
+    deltas: HashMap<(usize, usize), Option<Int>>,
+}
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+struct Cond {
+    expr: String,
+    // True if the condition holds when expr is non-zero
+    nonzero: bool,
+}
+
+impl Cond {
+    fn negate(&self) -> Cond {
+        Cond { expr: self.expr.clone(), nonzero: !self.nonzero }
+    }
+}
+
+impl Display for Cond {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        if self.nonzero {
+            write!(f, "{}", self.expr)
+        } else {
+            write!(f, "!{}", self.expr)
+        }
+    }
+}
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+enum Item {
+    Label(usize),
+    Stmt(String),
+    Jump(usize),
+    CondJump(Cond, usize),
+}
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+enum Node {
+    Item(Item),
+    If(Cond, Vec<Node>, Vec<Node>),
+    DoWhile(Vec<Node>, Cond),
+    Loop(Vec<Node>),
+}
+
+fn is_jump(insn: &Line) -> bool {
+    matches!(insn, Line::Insn { insn, .. } if matches!(insn.op.opcode, 5 | 6))
+}
+
+// The condition of a jt/jf, or None if it always jumps.
+fn jump_cond(line: &Line, delta: Option<Int>) -> Option<Cond> {
+    match line {
+        Line::Insn { insn, args, .. } if insn.modes[0] != Mode::Immediate => {
+            Some(Cond { expr: operand(args[0], insn.modes[0], delta), nonzero: insn.op.opcode == 5 })
+        }
+        _ => None,
+    }
+}
+
+// The return address stored by the instruction, if it stores a constant.
+fn stored_constant(line: &Line) -> Option<Int> {
+    let (insn, args) = match line {
+        Line::Insn { insn, args, .. } => (insn, args),
+        Line::Data { .. } => return None,
+    };
+    let imm = |n: usize| if insn.modes[n] == Mode::Immediate { Some(args[n]) } else { None };
+    match (insn.op.opcode, imm(0), imm(1)) {
+        (1, Some(a), Some(b)) => a.checked_add(b),
+        (2, Some(a), Some(b)) => a.checked_mul(b),
+        _ => None,
+    }
+}
+
+// If the block ends in a call, the function called and return address.
+fn call_of(block: &Block) -> Option<(usize, usize)> {
+    let target = match block.exit {
+        Exit::Jump(target) => target,
+        _ => return None,
+    };
+    let n = block.lines.len();
+    if n < 2 || !is_jump(&block.lines[n - 1]) {
+        return None;
+    }
+    let ret = block.end;
+    match stored_constant(&block.lines[n - 2]) {
+        Some(val) if usize::try_from(val) == Ok(ret) => Some((target, ret)),
+        _ => None,
+    }
+}
+
+fn slot_name(slot: Int) -> String {
+    if slot >= 0 {
+        format!("local{}", slot)
+    } else {
+        format!("param{}", slot.unsigned_abs())
+    }
+}
+
+fn operand(arg: Int, mode: Mode, delta: Option<Int>) -> String {
+    match (mode, delta) {
+        (Mode::Immediate, _) => arg.to_string(),
+        (Mode::Position, _) => format!("m{}", arg),
+        (Mode::Relative, delta) => match delta.and_then(|d| arg.checked_add(d)) {
+            Some(slot) => slot_name(slot),
+            None => format!("rb[{}]", arg),
+        },
+    }
+}
+
+fn is_imm(args: &[Int], modes: &[Mode; 3], n: usize, val: Int) -> bool {
+    modes[n] == Mode::Immediate && args[n] == val
+}
+
+// The statement for an instruction which isn't a jump, or None if it
+// just moves the frame.
+fn statement(line: &Line, delta: Option<Int>) -> Option<String> {
+    let (insn, args) = match line {
+        Line::Insn { insn, args, .. } => (insn, args),
+        Line::Data { value, .. } => return Some(format!("fault({})", value)),
+    };
+    let op = |n: usize| operand(args[n], insn.modes[n], delta);
+    let modes = &insn.modes;
+    Some(match insn.op.opcode {
+        1 if is_imm(args, modes, 0, 0) => format!("{} = {}", op(2), op(1)),
+        1 if is_imm(args, modes, 1, 0) => format!("{} = {}", op(2), op(0)),
+        1 if modes[1] == Mode::Immediate && args[1] < 0 => {
+            format!("{} = {} - {}", op(2), op(0), args[1].unsigned_abs())
+        }
+        1 => format!("{} = {} + {}", op(2), op(0), op(1)),
+        2 if is_imm(args, modes, 0, 1) => format!("{} = {}", op(2), op(1)),
+        2 if is_imm(args, modes, 1, 1) => format!("{} = {}", op(2), op(0)),
+        2 if is_imm(args, modes, 1, -1) => format!("{} = -{}", op(2), op(0)),
+        2 => format!("{} = {} * {}", op(2), op(0), op(1)),
+        3 => format!("{} = input()", op(0)),
+        4 => format!("output({})", op(0)),
+        7 => format!("{} = {} < {}", op(2), op(0), op(1)),
+        8 => format!("{} = {} == {}", op(2), op(0), op(1)),
+        9 if delta.is_some() && modes[0] == Mode::Immediate => return None,
+        9 => format!("rb += {}", op(0)),
+        99 => "halt".into(),
+        _ => format!("{}", line),
+    })
+}
+
+fn arb_delta(line: &Line, delta: Option<Int>) -> Option<Int> {
+    match line {
+        Line::Insn { insn, args, .. } if insn.op.opcode == 9 => {
+            if insn.modes[0] == Mode::Immediate { delta.and_then(|d| d.checked_add(args[0])) } else { None }
+        }
+        _ => delta,
+    }
+}
+
+impl Decompiled {
+    // Successors within the function: a call returns to after the jump.
+    fn successors(&self, block: &Block) -> Vec<usize> {
+        if let Some(&(_, ret)) = self.calls.get(&block.start) {
+            return vec![ret];
+        }
+        match block.exit {
+            Exit::Indirect(next) => next.into_iter().collect(),
+            exit => exit.successors(),
+        }
+    }
+
+    fn add_function(&mut self, entry: usize, name: String) {
+        let mut function = Function {
+            entry, name, blocks: Vec::new(), calls: BTreeSet::new(), slots: BTreeSet::new(), frame: None,
+        };
+        let mut todo = vec![(entry, Some(0))];
+        while let Some((start, delta)) = todo.pop() {
+            let block = match self.analysis.blocks.get(&start) {
+                Some(block) => block,
+                None => continue,
+            };
+            let delta = match self.deltas.get(&(entry, start)) {
+                None => delta,
+                Some(&old) if old == delta || old.is_none() => continue,
+                Some(_) => None,
+            };
+            self.deltas.insert((entry, start), delta);
+            // Slots used only to call and return aren't variables.
+            let n = block.lines.len();
+            let plumbing = if self.calls.contains_key(&start) {
+                n - 2
+            } else if block.exit == Exit::Indirect(None) {
+                n - 1
+            } else {
+                n
+            };
+            let mut d = delta;
+            for (i, line) in block.lines.iter().enumerate() {
+                if let (Line::Insn { insn, args, .. }, true) = (line, i < plumbing) {
+                    for (i, &mode) in insn.modes.iter().enumerate().take(insn.op.params) {
+                        if let (Mode::Relative, Some(slot)) = (mode, d.and_then(|d| args[i].checked_add(d))) {
+                            function.slots.insert(slot);
+                        }
+                    }
+                }
+                d = arb_delta(line, d);
+                if let Some(d) = d {
+                    function.frame = Some(function.frame.unwrap_or(0).max(d));
+                }
+            }
+            if let Some(&(target, _)) = self.calls.get(&start) {
+                function.calls.insert(target);
+            }
+            todo.extend(self.successors(block).into_iter().map(|s| (s, d)));
+        }
+        function.blocks = self.deltas.keys()
+                                     .filter(|(e, _)| *e == entry)
+                                     .map(|&(_, start)| start)
+                                     .collect();
+        function.blocks.sort_unstable();
+        self.functions.insert(entry, function);
+    }
+
+    // The function's code as a flat list, in address order.
+    fn items(&self, function: &Function) -> Vec<Item> {
+        let mut items = Vec::new();
+        for (i, start) in function.blocks.iter().enumerate() {
+            let block = &self.analysis.blocks[start];
+            let next_block = function.blocks.get(i + 1).cloned();
+            let goto = |items: &mut Vec<Item>, target: usize| {
+                if Some(target) != next_block {
+                    items.push(Item::Jump(target));
+                }
+            };
+            items.push(Item::Label(*start));
+            let frame = function.frame.unwrap_or(0);
+            let mut delta = self.deltas[&(function.entry, *start)].and_then(|d| d.checked_sub(frame));
+            let call = self.calls.get(start);
+            let skip = if call.is_some() { 2 } else if is_jump(block.lines.last().unwrap()) { 1 } else { 0 };
+            for line in &block.lines[..block.lines.len() - skip] {
+                if let Some(stmt) = statement(line, delta) {
+                    items.push(Item::Stmt(stmt));
+                }
+                delta = arb_delta(line, delta);
+            }
+            let last = block.lines.last().unwrap();
+            if let Some(&(target, ret)) = call {
+                items.push(Item::Stmt(format!("{}()", self.name(target))));
+                goto(&mut items, ret);
+                continue;
+            }
+            match block.exit {
+                Exit::Next(next) | Exit::Jump(next) => goto(&mut items, next),
+                Exit::Branch(target, next) => {
+                    items.push(Item::CondJump(jump_cond(last, delta).unwrap(), target));
+                    goto(&mut items, next);
+                }
+                Exit::Indirect(Some(next)) => {
+                    items.push(Item::Stmt(format!("if ({}) return", jump_cond(last, delta).unwrap())));
+                    goto(&mut items, next);
+                }
+                Exit::Indirect(None) => items.push(Item::Stmt("return".into())),
+                Exit::Halt | Exit::Fault => {}
+            }
+        }
+        items
+    }
+
+    fn name(&self, entry: usize) -> String {
+        self.functions.get(&entry).map_or_else(|| format!("f_{}", entry), |f| f.name.clone())
+    }
+
+    pub fn pseudocode(&self) -> String {
+        let mut result = String::new();
+        for function in self.functions.values() {
+            let items = self.items(function);
+            let nodes = Structurer::new(&items).structure(0, items.len());
+            let mut targets = BTreeSet::new();
+            gotos(&nodes, &mut targets);
+
+            let frame = function.frame.unwrap_or(0);
+            let offsets: Vec<_> = function.slots.iter().filter_map(|s| s.checked_sub(frame)).collect();
+            let params: Vec<_> = offsets.iter().filter(|&&o| o < 0).rev().map(|&o| slot_name(o)).collect();
+            result += &format!("fn {}({}) {{\n", function.name, params.join(", "));
+            let locals: Vec<_> = offsets.iter().filter(|&&o| o >= 0).map(|&o| slot_name(o)).collect();
+            if !locals.is_empty() {
+                result += &format!("    var {};\n", locals.join(", "));
+            }
+            write_nodes(&mut result, &nodes, 1, &targets);
+            result += "}\n\n";
+        }
+        result.pop();
+        result
+    }
+}
+
+// The jumps are found up front, with the jumps into each label, so that
+// checking a range only looks at the jumps in and into it.
+struct Structurer<'a> {
+    items: &'a [Item],
+    // Each jump's index and the index of its label, if any, in order
+    jumps: Vec<(usize, Option<usize>)>,
+    // The indices of the jumps to each label
+    incoming: BTreeMap<usize, Vec<usize>>,
+}
+
+impl<'a> Structurer<'a> {
+    fn new(items: &'a [Item]) -> Structurer<'a> {
+        let labels: HashMap<usize, usize> = items.iter().enumerate().filter_map(|(i, item)| match item {
+            Item::Label(addr) => Some((*addr, i)),
+            _ => None,
+        }).collect();
+        let jumps: Vec<_> = items.iter().enumerate().filter_map(|(i, item)| match item {
+            Item::Jump(addr) | Item::CondJump(_, addr) => Some((i, labels.get(addr).cloned())),
+            _ => None,
+        }).collect();
+        let mut incoming: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for &(i, target) in &jumps {
+            if let Some(t) = target {
+                incoming.entry(t).or_default().push(i);
+            }
+        }
+        Structurer { items, jumps, incoming }
+    }
+
+    // The index of the label a jump at `i` goes to, if any.
+    fn target(&self, i: usize) -> Option<Option<usize>> {
+        self.jumps.binary_search_by_key(&i, |&(j, _)| j).ok().map(|n| self.jumps[n].1)
+    }
+
+    // Whether items lo..hi are only entered at the top, and only jump
+    // to labels between `from` and `to` inclusive.  The jumps at `ignore`
+    // are the ones being structured.
+    fn contained(&self, lo: usize, hi: usize, from: usize, to: usize, ignore: &[usize]) -> bool {
+        let first = self.jumps.partition_point(|&(i, _)| i < lo);
+        let exits_ok = self.jumps[first..].iter()
+                                          .take_while(|&&(i, _)| i < hi)
+                                          .filter(|(i, _)| !ignore.contains(i))
+                                          .all(|&(_, target)| target.is_some_and(|t| t >= from && t <= to));
+        exits_ok && !self.incoming.range(lo..hi.max(lo))
+                                  .flat_map(|(_, sources)| sources)
+                                  .any(|i| !(lo..hi).contains(i) && !ignore.contains(i))
+    }
+
+    fn structure(&self, lo: usize, hi: usize) -> Vec<Node> {
+        let mut result = Vec::new();
+        let mut i = lo;
+        while i < hi {
+            let item = &self.items[i];
+            if let Item::Label(_) = item {
+                // The furthest jump back to here which closes a loop
+                let sources = self.incoming.get(&i).map_or(&[][..], |s| &s[..]);
+                let back = sources.iter().rev().cloned().find(|&j| {
+                    j > i && j < hi && self.contained(i + 1, j, i, j + 1, &[j])
+                });
+                if let Some(j) = back {
+                    result.push(Node::Item(item.clone()));
+                    let body = self.structure(i + 1, j);
+                    result.push(match &self.items[j] {
+                        Item::CondJump(cond, _) => Node::DoWhile(body, cond.clone()),
+                        _ => Node::Loop(body),
+                    });
+                    i = j + 1;
+                    continue;
+                }
+            }
+            if let (Item::CondJump(cond, _), Some(Some(t))) = (item, self.target(i)) {
+                // An else part follows if the then part ends by jumping
+                // over it.
+                let other = match self.target(t.saturating_sub(1)) {
+                    Some(Some(t2)) if t > i + 1 && matches!(self.items[t - 1], Item::Jump(_))
+                        && t2 > t && t2 <= hi
+                        && self.contained(i + 1, t - 1, i + 1, t - 1, &[i])
+                        && self.contained(t, t2, t, t2, &[i, t - 1]) => Some(t2),
+                    _ => None,
+                };
+                if let Some(t2) = other {
+                    result.push(Node::If(cond.negate(), self.structure(i + 1, t - 1), self.structure(t, t2)));
+                    i = t2;
+                    continue;
+                }
+                if t > i && t <= hi && self.contained(i + 1, t, i + 1, t, &[i]) {
+                    result.push(Node::If(cond.negate(), self.structure(i + 1, t), Vec::new()));
+                    i = t;
+                    continue;
+                }
+            }
+            result.push(Node::Item(item.clone()));
+            i += 1;
+        }
+        result
+    }
+}
+
+// Labels still jumped to by gotos
+fn gotos(nodes: &[Node], targets: &mut BTreeSet<usize>) {
+    for node in nodes {
+        match node {
+            Node::Item(Item::Jump(t)) | Node::Item(Item::CondJump(_, t)) => {
+                targets.insert(*t);
+            }
+            Node::If(_, a, b) => {
+                gotos(a, targets);
+                gotos(b, targets);
+            }
+            Node::DoWhile(body, _) | Node::Loop(body) => gotos(body, targets),
+            Node::Item(_) => {}
+        }
+    }
+}
+
+fn write_nodes(out: &mut String, nodes: &[Node], depth: usize, targets: &BTreeSet<usize>) {
+    let indent = "    ".repeat(depth);
+    for node in nodes {
+        match node {
+            Node::Item(Item::Label(addr)) => {
+                if targets.contains(addr) {
+                    *out += &format!("{}L{}:\n", "    ".repeat(depth - 1), addr);
+                }
+            }
+            Node::Item(Item::Stmt(s)) => *out += &format!("{}{};\n", indent, s),
+            Node::Item(Item::Jump(t)) => *out += &format!("{}goto L{};\n", indent, t),
+            Node::Item(Item::CondJump(cond, t)) => {
+                *out += &format!("{}if ({}) goto L{};\n", indent, cond, t)
+            }
+            Node::If(cond, then, other) => {
+                *out += &format!("{}if ({}) {{\n", indent, cond);
+                write_nodes(out, then, depth + 1, targets);
+                if !other.is_empty() {
+                    *out += &format!("{}}} else {{\n", indent);
+                    write_nodes(out, other, depth + 1, targets);
+                }
+                *out += &format!("{}}}\n", indent);
+            }
+            Node::DoWhile(body, cond) => {
+                *out += &format!("{}do {{\n", indent);
+                write_nodes(out, body, depth + 1, targets);
+                *out += &format!("{}}} while ({});\n", indent, cond);
+            }
+            Node::Loop(body) => {
+                *out += &format!("{}loop {{\n", indent);
+                write_nodes(out, body, depth + 1, targets);
+                *out += &format!("{}}}\n", indent);
+            }
+        }
+    }
+}
+
+// Decompile the code reachable from `entries`; the first is called main.
+pub fn decompile<M: Words + ?Sized>(mem: &M, entries: &[usize]) -> Decompiled {
+    // Calls hide the code they return to from the analysis, so keep going
+    // until no new return addresses turn up.
+    let mut roots: BTreeSet<usize> = entries.iter().cloned().collect();
+    let (analysis, calls) = loop {
+        let analysis = analyse(mem, &roots.iter().cloned().collect::<Vec<_>>());
+        let calls: HashMap<_, _> = analysis.blocks.values()
+                                           .filter_map(|b| call_of(b).map(|c| (b.start, c)))
+                                           .collect();
+        let before = roots.len();
+        roots.extend(calls.values().flat_map(|&(target, ret)| [target, ret]));
+        if roots.len() == before {
+            break (analysis, calls);
+        }
+    };
+    let mut result = Decompiled {
+        analysis, functions: BTreeMap::new(), calls, deltas: HashMap::new(),
+    };
+    let mut entry_names: Vec<(usize, String)> = entries.iter().enumerate().map(|(i, &e)| {
+        (e, if i == 0 { "main".to_string() } else { format!("f_{}", e) })
+    }).collect();
+    let targets: BTreeSet<usize> = result.calls.values().map(|&(target, _)| target).collect();
+    entry_names.extend(targets.into_iter().filter(|t| !entries.contains(t)).map(|t| (t, format!("f_{}", t))));
+    for (entry, name) in entry_names {
+        result.add_function(entry, name);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::asm::assemble;
+    use super::super::IntcodeMachine;
+
+    #[test]
+    fn test_loops() {
+        let prog = assemble(r#"
+                    in   n
+            loop:   add  total, n, total
+                    add  n, #-1, n
+                    jt   n, #loop
+                    lt   total, #10, t
+                    jf   t, #big
+                    out  #0
+                    jt   #1, #done
+            big:    out  total
+            done:   hlt
+            n:      data 0
+            total:  data 0
+            t:      data 0
+        "#).unwrap();
+        let text = decompile(&prog, &[0]).pseudocode();
+        assert_eq!(text, "\
+fn main() {
+    m28 = input();
+    do {
+        m29 = m29 + m28;
+        m28 = m28 - 1;
+    } while (m28);
+    m30 = m29 < 10;
+    if (m30) {
+        output(0);
+    } else {
+        output(m29);
+    }
+    halt;
+}
+");
+    }
+
+    // main calls double(21) and outputs the result.  Functions take their
+    // return address at @0 and argument at @1, and return a value in @1.
+    const CALLS: &str = r#"
+                arb  #stack
+                add  #21, #0, @1
+                add  #back, #0, @0
+                jt   #1, #double
+        back:   out  @1
+                hlt
+        double: arb  #2
+                mul  @-1, #2, @0
+                lt   @0, #100, @1
+                jf   @1, #skip
+                add  @0, #0, @-1
+        skip:   arb  #-2
+                jt   #1, @0
+        stack:  data 0
+    "#;
+
+    #[test]
+    fn test_functions() {
+        let prog = assemble(CALLS).unwrap();
+        let mut machine = IntcodeMachine::new(&prog);
+        machine.run_until_halt().unwrap();
+        assert_eq!(machine.get_outputs(), &[42]);
+
+        let decompiled = decompile(&prog, &[0]);
+        assert_eq!(decompiled.functions.keys().cloned().collect::<Vec<_>>(), vec![0, 16]);
+        let double = &decompiled.functions[&16];
+        assert_eq!(double.frame, Some(2));
+        assert_eq!(decompiled.functions[&0].calls.iter().cloned().collect::<Vec<_>>(), vec![16]);
+        assert!(decompiled.analysis.unreachable.is_empty());
+        assert_eq!(decompiled.pseudocode(), "\
+fn main() {
+    var local1;
+    local1 = 21;
+    f_16();
+    output(local1);
+    halt;
+}
+
+fn f_16(param1) {
+    var local0, local1;
+    local0 = param1 * 2;
+    local1 = local0 < 100;
+    if (local1) {
+        param1 = local0;
+    }
+    return;
+}
+");
+    }
+
+    #[test]
+    fn test_overflow() {
+        // Constants at the ends of the range, which can't be folded or
+        // taken as frame offsets, are left as they are.
+        let max = Int::MAX;
+        let prog = [109, max, 109, max, 204, max, 1001, 20, Int::MIN, 20,
+                    21101, max, 1, 0, 1101, max, 1, 20, 1105, 1, 21, 99];
+        let text = decompile(&prog, &[0]).pseudocode();
+        assert!(text.contains(&format!("output(rb[{}]);", max)));
+        assert!(text.contains("m20 = m20 - 9223372036854775808;"));
+        // Not a call, as the return address overflows
+        assert!(text.contains(&format!("m20 = {} + 1;", max)));
+        assert!(!text.contains("f_21"));
+    }
+
+    #[test]
+    fn test_many_branches() {
+        // Enough branches that checking each against every item would be slow.
+        use super::super::compile::compile;
+        let mut source = String::from("fn main() { var x = input(); while x { ");
+        for i in 0..1000 {
+            source += &format!("if x == {} {{ output({}); }} ", i, i);
+        }
+        source += "x = x - 1; } }";
+        let text = decompile(&compile(&source).unwrap(), &[0]).pseudocode();
+        assert_eq!(text.matches("if (local2) {").count(), 1000);
+    }
+}
@@ -0,0 +1,240 @@
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use super::{Int, IntcodeMachine, Input, Output, Error, Word};
+
+// Devices attached to ranges of addresses take the place of memory
+// there: data reads and writes by instructions (and through `get_u`
+// and `set`) go to the device, given the offset into its range.
+// Instructions are still fetched from memory, and `get_data`, the
+// disassembler and tracers see the memory underneath.  Device writes
+// can't be undone by the history.
+
+pub trait Device<W = Int> {
+    fn read(&mut self, offset: usize) -> W;
+    fn write(&mut self, offset: usize, val: W);
+}
+
+pub type SharedDevice<W = Int> = Arc<Mutex<dyn Device<W> + Send>>;
+
+// A frame buffer of width x height words, row by row.  It must be at
+// least one word wide, with a size which fits a usize.
+#[derive(Debug,Clone)]
+pub struct Screen<W = Int> {
+    width: usize,
+    height: usize,
+    pixels: Vec<W>,
+}
+
+impl<W: Word> Screen<W> {
+    pub fn new(width: usize, height: usize) -> Option<Screen<W>> {
+        if width == 0 {
+            return None;
+        }
+        let size = width.checked_mul(height)?;
+        Some(Screen { width, height, pixels: vec![W::default(); size] })
+    }
+
+    pub fn size(&self) -> usize {
+        self.width * self.height
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Option<W> {
+        if x < self.width && y < self.height {
+            Some(self.pixels[y * self.width + x].clone())
+        } else {
+            None
+        }
+    }
+
+    // One line per row, using `f` to show each pixel.
+    pub fn render(&self, f: impl Fn(&W) -> char) -> String {
+        let mut result = String::new();
+        for row in self.pixels.chunks(self.width) {
+            result.extend(row.iter().map(&f));
+            result.push('\n');
+        }
+        result
+    }
+}
+
+impl<W: Word> Device<W> for Screen<W> {
+    fn read(&mut self, offset: usize) -> W {
+        self.pixels.get(offset).cloned().unwrap_or_default()
+    }
+
+    fn write(&mut self, offset: usize, val: W) {
+        if let Some(pixel) = self.pixels.get_mut(offset) {
+            *pixel = val;
+        }
+    }
+}
+
+// Pseudo-random numbers (xorshift), from 0 up to but not including the
+// range given.  Writing reseeds it.
+#[derive(Debug,Clone)]
+pub struct Random {
+    state: u64,
+    range: Int,
+}
+
+impl Random {
+    pub fn new(seed: u64, range: Int) -> Random {
+        Random { state: seed.max(1), range }
+    }
+}
+
+impl<W: Word> Device<W> for Random {
+    fn read(&mut self, _offset: usize) -> W {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        W::from_int((self.state % self.range.max(1) as u64) as Int)
+    }
+
+    fn write(&mut self, _offset: usize, val: W) {
+        self.state = val.to_int().map_or(1, |v| (v as u64).max(1));
+    }
+}
+
+// Reads give the milliseconds since it was started; writing restarts it.
+#[derive(Debug,Clone)]
+pub struct Timer {
+    start: Instant,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer { start: Instant::now() }
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Timer {
+        Timer::new()
+    }
+}
+
+impl<W: Word> Device<W> for Timer {
+    fn read(&mut self, _offset: usize) -> W {
+        W::from_int(self.start.elapsed().as_millis() as Int)
+    }
+
+    fn write(&mut self, _offset: usize, _val: W) {
+        self.start = Instant::now();
+    }
+}
+
+impl<I: Input<W>, O: Output<W>, W: Word> IntcodeMachine<I, O, W> {
+    // Returns false (attaching nothing) if the range overlaps another
+    // device's.
+    pub fn attach_device(&mut self, range: Range<usize>, device: SharedDevice<W>) -> bool {
+        if self.devices.iter().any(|(r, _)| r.start < range.end && range.start < r.end) {
+            return false;
+        }
+        self.devices.push((range, device));
+        true
+    }
+
+    // Detach the device whose range starts at `start`.
+    pub fn detach_device(&mut self, start: usize) -> Option<SharedDevice<W>> {
+        let index = self.devices.iter().position(|(r, _)| r.start == start)?;
+        Some(self.devices.remove(index).1)
+    }
+
+    pub(super) fn device_at(&self, addr: usize) -> Option<(usize, &SharedDevice<W>)> {
+        self.devices.iter()
+                    .find(|(r, _)| r.contains(&addr))
+                    .map(|(r, device)| (addr - r.start, device))
+    }
+
+    // A data read, from a device if there is one at `addr`.
+    pub(super) fn load(&self, addr: usize) -> Result<W, Error<W>> {
+        if !self.devices.is_empty() {
+            if let Some((offset, device)) = self.device_at(addr) {
+                let mut device = device.lock().map_err(|_| Error::DevicePoisoned { pc: self.pc, addr })?;
+                return Ok(device.read(offset));
+            }
+        }
+        Ok(self.data.get(addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::asm::assemble;
+
+    // Draws a diagonal line on a 4x3 screen mapped at 100.
+    const DIAGONAL: &str = r#"
+                arb  #100
+        loop:   add  #1, #0, @0
+                add  @0, @-5, @100  ; also copies a screen pixel to 200
+                arb  #5
+                add  n, #-1, n
+                jt   n, #loop
+                hlt
+        n:      data 3
+    "#;
+
+    #[test]
+    fn test_screen() {
+        for cached in [false, true] {
+            let mut machine = IntcodeMachine::new(&assemble(DIAGONAL).unwrap());
+            machine.set_decode_cache(cached);
+            let screen = Arc::new(Mutex::new(Screen::new(4, 3).unwrap()));
+            assert!(machine.attach_device(100..112, screen.clone()));
+            machine.run_until_halt().unwrap();
+            assert_eq!(screen.lock().unwrap().render(|&p| if p != 0 { '#' } else { '.' }),
+                       "#...\n.#..\n..#.\n");
+            assert_eq!(screen.lock().unwrap().pixel(1, 1), Some(1));
+            assert_eq!(screen.lock().unwrap().pixel(4, 0), None);
+            assert_eq!(screen.lock().unwrap().pixel(0, 3), None);
+            // Memory underneath is untouched.
+            assert_eq!(machine.get_data(100, 12), vec![0; 12]);
+            assert_eq!(machine.get_u(105), Ok(1));
+            assert_eq!(machine.get_data(200, 11), vec![1, 0, 0, 0, 0, 2, 0, 0, 0, 0, 2]);
+        }
+    }
+
+    #[test]
+    fn test_attach() {
+        let mut machine = IntcodeMachine::new(&[4, 50, 4, 50, 99]);
+        let random: SharedDevice = Arc::new(Mutex::new(Random::new(1, 6)));
+        assert!(machine.attach_device(50..51, random.clone()));
+        assert!(!machine.attach_device(40..60, Arc::new(Mutex::new(Timer::new()))));
+        machine.run_until_halt().unwrap();
+        let outputs = machine.take_outputs();
+        assert!(outputs.iter().all(|v| (0..6).contains(v)));
+
+        // Reseeding repeats the sequence.
+        machine.set(50, 1).unwrap();
+        machine.set_pc(0);
+        machine.run_until_halt().unwrap();
+        assert_eq!(machine.take_outputs(), outputs);
+
+        assert!(machine.detach_device(50).is_some());
+        assert!(machine.detach_device(50).is_none());
+        assert_eq!(machine.get_u(50), Ok(0));
+    }
+
+    #[test]
+    fn test_poisoned() {
+        assert!(Screen::<Int>::new(0, 3).is_none());
+        assert_eq!(Screen::<Int>::new(2, 0).unwrap().render(|_| '.'), "");
+        assert!(Screen::<Int>::new(usize::MAX, 2).is_none());
+
+        // A device which panicked while locked fails every access after.
+        let screen: SharedDevice = Arc::new(Mutex::new(Screen::new(2, 2).unwrap()));
+        let held = screen.clone();
+        assert!(std::thread::spawn(move || {
+            let _lock = held.lock().unwrap();
+            panic!("device failed");
+        }).join().is_err());
+        let mut machine = IntcodeMachine::new(&[1101, 1, 2, 50, 99]);
+        assert!(machine.attach_device(50..54, screen));
+        assert_eq!(machine.run_until_halt(), Err(Error::DevicePoisoned { pc: 0, addr: 50 }));
+        assert_eq!(machine.set(51, 7), Err(Error::DevicePoisoned { pc: 0, addr: 51 }));
+        assert_eq!(machine.get_u(52), Err(Error::DevicePoisoned { pc: 0, addr: 52 }));
+    }
+}
@@ -0,0 +1,208 @@
+use std::fmt::Display;
+use super::{Int, Mode, Instruction, IntcodeMachine, Input, Output, decode};
+use super::memory::Words;
+
+// Disassembly produces the syntax accepted by `asm::assemble`, so that a
+// listing can be edited and reassembled.  Anything which doesn't decode
+// as a valid instruction is written as `data`.
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum Line<W = Int> {
+    Insn { addr: usize, insn: Instruction, args: Vec<W> },
+    Data { addr: usize, value: W },
+}
+
+impl<W> Line<W> {
+    pub fn addr(&self) -> usize {
+        match self {
+            Line::Insn { addr, .. } => *addr,
+            Line::Data { addr, .. } => *addr,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            Line::Insn { insn, .. } => insn.size(),
+            Line::Data { .. } => 1,
+        }
+    }
+}
+
+impl Line {
+    // Describe the operands' modes and values.  Addresses are resolved
+    // against `mem`, and relative ones also against `rel_base` if known.
+    pub fn describe<M: Words + ?Sized>(&self, mem: &M, rel_base: Option<Int>) -> String {
+        let (insn, args) = match self {
+            Line::Insn { insn, args, .. } => (insn, args),
+            Line::Data { .. } => return "data".into(),
+        };
+        let fetch = |addr: Int| {
+            usize::try_from(addr).ok()
+                .map(|a| mem.word(a).unwrap_or(0))
+        };
+        let mut result = Vec::new();
+        for (i, (&arg, &mode)) in args.iter().zip(insn.modes.iter()).enumerate() {
+            let write = insn.op.writes && i == insn.op.params - 1;
+            let (name, text, addr) = match mode {
+                Mode::Immediate => {
+                    result.push(format!("imm {}", arg));
+                    continue;
+                }
+                Mode::Position => ("pos", format!("[{}]", arg), Some(arg)),
+                Mode::Relative => match rel_base.and_then(|base| base.checked_add(arg)) {
+                    Some(addr) => ("rel", format!("[rb{:+}={}]", arg, addr), Some(addr)),
+                    None => ("rel", format!("[rb{:+}]", arg), None),
+                },
+            };
+            result.push(match (write, addr.and_then(fetch)) {
+                (true, _) => format!("{} ->{}", name, text),
+                (false, Some(val)) => format!("{} {}={}", name, text, val),
+                (false, None) => format!("{} {}", name, text),
+            });
+        }
+        result.join(", ")
+    }
+}
+
+impl<W: Display> Display for Line<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            Line::Insn { insn, args, .. } => {
+                let operands = args.iter()
+                                   .zip(insn.modes.iter())
+                                   .map(|(arg, mode)| match mode {
+                                       Mode::Position => format!("{}", arg),
+                                       Mode::Immediate => format!("#{}", arg),
+                                       Mode::Relative => format!("@{}", arg),
+                                   })
+                                   .collect::<Vec<_>>();
+                if operands.is_empty() {
+                    write!(f, "{}", insn.op.mnemonic)
+                } else {
+                    write!(f, "{:<4}{}", insn.op.mnemonic, operands.join(", "))
+                }
+            }
+            Line::Data { value, .. } => write!(f, "data {}", value),
+        }
+    }
+}
+
+pub fn decode_line<M: Words + ?Sized>(mem: &M, addr: usize) -> Line {
+    let value = mem.word(addr).unwrap_or(0);
+    if let Some(insn) = decode(value) {
+        // An instruction running off the end of memory is left as data
+        // so that reassembling gives back the same length.
+        let args = (addr + 1..addr + insn.size()).map(|a| mem.word(a))
+                                                 .collect::<Option<Vec<_>>>();
+        if let Some(args) = args {
+            return Line::Insn { addr, insn, args };
+        }
+    }
+    Line::Data { addr, value }
+}
+
+// Memory outside the loaded ranges is zero, and left out.
+pub fn disassemble<M: Words + ?Sized>(mem: &M, start: usize) -> Vec<Line> {
+    let mut result = Vec::new();
+    let mut addr = start;
+    for (low, end) in mem.loaded() {
+        addr = addr.max(low);
+        while addr < end {
+            let line = decode_line(mem, addr);
+            addr += line.size();
+            result.push(line);
+        }
+    }
+    result
+}
+
+pub fn listing<M: Words + ?Sized>(mem: &M, lines: &[Line], rel_base: Option<Int>) -> String {
+    let mut result = String::new();
+    for line in lines {
+        let text = line.to_string();
+        let text = format!("{:<28}; {:05}: {}", text, line.addr(), line.describe(mem, rel_base));
+        result += text.trim_end();
+        result.push('\n');
+    }
+    result
+}
+
+impl<I: Input, O: Output> IntcodeMachine<I, O> {
+    pub fn disassemble(&self, start: usize, count: usize) -> String {
+        let mut lines = Vec::new();
+        let mut addr = start;
+        while lines.len() < count && addr < self.data.len() {
+            let line = decode_line(&self.data, addr);
+            addr += line.size();
+            lines.push(line);
+        }
+        listing(&self.data, &lines, Some(self.rel_base))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::asm::assemble;
+
+    #[test]
+    fn test_decode() {
+        let mem = [1002, 4, 3, 4, 33];
+        let lines = disassemble(&mem, 0);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].to_string(), "mul 4, #3, 4");
+        assert_eq!(lines[0].describe(&mem, None), "pos [4]=33, imm 3, pos ->[4]");
+        assert_eq!(lines[1], Line::Data { addr: 4, value: 33 });
+
+        let mem = [109, 10, 204, -3, 99];
+        let lines = disassemble(&mem, 2);
+        assert_eq!(lines[0].to_string(), "out @-3");
+        assert_eq!(lines[0].describe(&mem, Some(4)), "rel [rb-3=1]=10");
+        assert_eq!(lines[1].to_string(), "hlt");
+
+        // An address out of range is left unresolved.
+        let mem = [204, Int::MAX];
+        let line = decode_line(&mem[..], 0);
+        assert_eq!(line.describe(&mem, Some(1)), format!("rel [rb+{}]", Int::MAX));
+    }
+
+    #[test]
+    fn test_sparse() {
+        let mut machine = IntcodeMachine::new(&[1101, 0, 99, 1 << 40, 99]);
+        machine.run_until_halt().unwrap();
+        let addrs: Vec<_> = disassemble(machine.memory(), 0).iter().map(|l| l.addr()).collect();
+        assert_eq!(addrs, vec![0, 4, 1 << 40]);
+    }
+
+    #[test]
+    fn test_not_instructions() {
+        // Unknown opcode, bad mode, immediate write, stray mode digit,
+        // and an instruction truncated by the end of memory.
+        let mem = [42, 301, 11101, 10099, 1];
+        let lines = disassemble(&mem, 0);
+        assert!(lines.iter().all(|l| matches!(l, Line::Data { .. })));
+        assert_eq!(lines.len(), mem.len());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let progs: &[&[Int]] = &[
+            &[109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99],
+            &[3,9,8,9,10,9,4,9,99,-1,8],
+            &[1102,34915192,34915192,7,4,7,99,0],
+            &[42, 301, 11101, 10099, 1, 1],
+        ];
+        for prog in progs {
+            let text = listing(prog, &disassemble(prog, 0), None);
+            assert_eq!(&assemble(&text).unwrap()[..], *prog);
+        }
+    }
+
+    #[test]
+    fn test_machine() {
+        let mut machine = IntcodeMachine::new(&[109, 5, 204, -3, 99]);
+        machine.step().unwrap();
+        assert_eq!(machine.disassemble(2, 1),
+                   format!("{:<28}; 00002: rel [rb-3=2]=204\n", "out @-3"));
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use super::{Int, Word, WideMachine, Error, OPCODES, decode};
+
+// Differential testing: random well-formed programs, with random input,
+// are run on several engines (ways of executing a program) which must
+// agree on the outputs, the final memory and state, and any error.
+// When they don't, the program and input are shrunk to a smaller case
+// on which the same two engines still disagree.
+//
+// Everything is driven by a seed, so a failing case can be regenerated
+// with `generate`.
+
+// What running a program came to.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Outcome {
+    pub outputs: Vec<Int>,
+    // The non-zero cells, since memory grows differently
+    pub memory: BTreeMap<usize, Int>,
+    pub pc: usize,
+    pub rel_base: Int,
+    pub steps: u64,
+    pub error: Option<String>,
+}
+
+pub trait Engine {
+    fn name(&self) -> &str;
+    fn run(&self, program: &[Int], inputs: &[Int], step_limit: u64) -> Outcome;
+}
+
+fn run_machine<W: Word>(mut machine: WideMachine<W>, inputs: &[Int], step_limit: u64,
+                        run: impl FnOnce(&mut WideMachine<W>) -> Result<(), Error<W>>) -> Outcome {
+    for &val in inputs {
+        machine.send_input(W::from_int(val));
+    }
+    machine.set_step_limit(Some(step_limit));
+    let result = run(&mut machine);
+    let int = |w: &W| w.to_int().expect("word out of range");
+    let memory = machine.memory();
+    let pages = memory.pages();
+    let memory = std::iter::once((0, memory.dense()))
+        .chain(pages)
+        .flat_map(|(start, words)| words.iter().enumerate().map(move |(i, w)| (start + i, int(w))))
+        .filter(|&(_, val)| val != 0)
+        .collect();
+    Outcome {
+        outputs: machine.take_outputs().iter().map(int).collect(),
+        memory,
+        pc: machine.pc(),
+        rel_base: int(&machine.rel_base()),
+        steps: machine.steps(),
+        error: result.err().map(|e| e.to_string()),
+    }
+}
+
+// The interpreter as it normally runs
+pub struct Interpreter;
+
+impl Engine for Interpreter {
+    fn name(&self) -> &str {
+        "interpreter"
+    }
+
+    fn run(&self, program: &[Int], inputs: &[Int], step_limit: u64) -> Outcome {
+        run_machine(WideMachine::<Int>::wide(program), inputs, step_limit, |m| m.run_until_halt())
+    }
+}
+
+// With the decode cache
+pub struct Cached;
+
+impl Engine for Cached {
+    fn name(&self) -> &str {
+        "cached"
+    }
+
+    fn run(&self, program: &[Int], inputs: &[Int], step_limit: u64) -> Outcome {
+        let mut machine = WideMachine::<Int>::wide(program);
+        machine.set_decode_cache(true);
+        run_machine(machine, inputs, step_limit, |m| m.run_until_halt())
+    }
+}
+
+// With i64 words, through the generic word code.  (The same as Int on
+// 64-bit targets, overflow included.)
+pub struct Wide;
+
+impl Engine for Wide {
+    fn name(&self) -> &str {
+        "wide"
+    }
+
+    fn run(&self, program: &[Int], inputs: &[Int], step_limit: u64) -> Outcome {
+        run_machine(WideMachine::<i64>::wide(program), inputs, step_limit, |m| m.run_until_halt())
+    }
+}
+
+// Runs with history, rewinds to the start and runs again, so any state
+// the history fails to restore shows up.
+pub struct Rewound;
+
+impl Engine for Rewound {
+    fn name(&self) -> &str {
+        "rewound"
+    }
+
+    fn run(&self, program: &[Int], inputs: &[Int], step_limit: u64) -> Outcome {
+        let mut machine = WideMachine::<Int>::wide(program);
+        machine.enable_history(None);
+        let mut rewound = true;
+        let mut outcome = run_machine(machine, inputs, step_limit, |m| {
+            let _ = m.run_until_halt();
+            rewound = m.rewind_to(0);
+            if rewound { m.run_until_halt() } else { Ok(()) }
+        });
+        // Reported as an outcome so that it's shrunk like any other failure
+        if !rewound {
+            outcome.error = Some("couldn't rewind to the start".into());
+        }
+        outcome
+    }
+}
+
+pub fn standard_engines() -> Vec<Box<dyn Engine>> {
+    vec![Box::new(Interpreter), Box::new(Cached), Box::new(Wide), Box::new(Rewound)]
+}
+
+// xorshift64
+#[derive(Debug,Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // Mixed so that neighbouring seeds start far apart
+        let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1);
+        rng.next_u64();
+        rng
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // From 0 up to but not including n
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n.max(1) as u64) as usize
+    }
+
+    pub fn range(&mut self, low: Int, high: Int) -> Int {
+        low + self.below((high - low) as usize + 1) as Int
+    }
+}
+
+// A random program of up to `max_insns` instructions (plus a halt and
+// some data), and input for it.  Every instruction is valid, with valid
+// modes; arguments are mostly addresses within the program, small
+// offsets from the relative base, small values, and for jumps the start
+// of an instruction.  Programs can still fault, loop or modify themselves.
+pub fn generate(seed: u64, max_insns: usize) -> (Vec<Int>, Vec<Int>) {
+    let mut rng = Rng::new(seed);
+    let count = 1 + rng.below(max_insns.max(1));
+    let ops: Vec<_> = (0..count).map(|_| &OPCODES[rng.below(OPCODES.len())]).collect();
+    let mut starts = Vec::new();
+    let mut code_len = 0;
+    for op in &ops {
+        starts.push(code_len as Int);
+        code_len += 1 + op.params;
+    }
+    starts.push(code_len as Int);
+    let len = code_len + 1 + rng.below(8);
+
+    let mut program = Vec::with_capacity(len);
+    for op in ops {
+        let mut insn = op.opcode;
+        let mut args = Vec::new();
+        for n in 0..op.params {
+            let write = op.writes && n + 1 == op.params;
+            let mode = if write { [0, 2][rng.below(2)] } else { rng.below(3) as Int };
+            let jump = (op.opcode == 5 || op.opcode == 6) && n == 1;
+            args.push(match mode {
+                0 if rng.below(8) == 0 => rng.range(0, len as Int + 20),
+                0 => rng.range(0, len as Int - 1),
+                1 if jump => starts[rng.below(starts.len())],
+                1 => rng.range(-20, 100),
+                _ => rng.range(-3, 10),
+            });
+            insn += mode * [100, 1000, 10000][n];
+        }
+        program.push(insn);
+        program.extend(args);
+    }
+    program.push(99);
+    while program.len() < len {
+        program.push(rng.range(-20, 100));
+    }
+    let inputs = (0..rng.below(6)).map(|_| rng.range(-20, 100)).collect();
+    (program, inputs)
+}
+
+// Two engines which disagreed about a program
+#[derive(Debug,Clone)]
+pub struct Mismatch {
+    // The case's seed for `generate`
+    pub seed: u64,
+    pub program: Vec<Int>,
+    pub inputs: Vec<Int>,
+    pub engines: [String; 2],
+    pub outcomes: [Outcome; 2],
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        writeln!(f, "{} and {} disagree (seed {})", self.engines[0], self.engines[1], self.seed)?;
+        writeln!(f, "  program: {:?}", self.program)?;
+        writeln!(f, "  inputs: {:?}", self.inputs)?;
+        for (name, outcome) in self.engines.iter().zip(&self.outcomes) {
+            writeln!(f, "  {}: {:?}", name, outcome)?;
+        }
+        Ok(())
+    }
+}
+
+// The first engine which disagrees with the first, with both outcomes.
+pub fn compare(engines: &[&dyn Engine], program: &[Int], inputs: &[Int],
+               step_limit: u64) -> Option<(usize, Outcome, Outcome)> {
+    let (first, rest) = engines.split_first()?;
+    let expected = first.run(program, inputs, step_limit);
+    rest.iter().enumerate().find_map(|(i, engine)| {
+        let outcome = engine.run(program, inputs, step_limit);
+        (outcome != expected).then(|| (i + 1, expected.clone(), outcome))
+    })
+}
+
+// Shrink a failing program and input to a smaller pair for which
+// `fails` still holds: first by removing runs of values (in the program
+// also trying with later addresses moved down to match), then by making
+// values closer to zero, for as long as either helps.
+pub fn shrink(program: &[Int], inputs: &[Int],
+              fails: impl Fn(&[Int], &[Int]) -> bool) -> (Vec<Int>, Vec<Int>) {
+    let mut case = [program.to_vec(), inputs.to_vec()];
+    let check = |case: &[Vec<Int>; 2]| fails(&case[0], &case[1]);
+    loop {
+        let mut progress = false;
+        for which in 0..2 {
+            let mut size = case[which].len() / 2;
+            while size > 0 {
+                let mut start = 0;
+                while start + size <= case[which].len() {
+                    let mut candidate = case.clone();
+                    candidate[which].drain(start..start + size);
+                    let mut relocated = candidate.clone();
+                    if which == 0 {
+                        relocate(&mut relocated[0], start + size, size);
+                    }
+                    if let Some(smaller) = [candidate, relocated].into_iter().find(check) {
+                        case = smaller;
+                        progress = true;
+                    } else {
+                        start += size;
+                    }
+                }
+                size /= 2;
+            }
+        }
+        for which in 0..2 {
+            for i in 0..case[which].len() {
+                let val = case[which][i];
+                for simpler in [0, val / 2] {
+                    if simpler.unsigned_abs() < val.unsigned_abs() {
+                        let mut candidate = case.clone();
+                        candidate[which][i] = simpler;
+                        if check(&candidate) {
+                            case = candidate;
+                            progress = true;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        if !progress {
+            let [program, inputs] = case;
+            return (program, inputs);
+        }
+    }
+}
+
+// After removing `size` values before `end`, move down the values
+// which might be addresses from `end` on, other than instruction words.
+fn relocate(program: &mut [Int], end: usize, size: usize) {
+    let moved = end as Int..(program.len() + size) as Int;
+    let mut next_insn = 0;
+    for (addr, val) in program.iter_mut().enumerate() {
+        if addr == next_insn {
+            if let Some(insn) = decode(*val) {
+                next_insn += insn.size();
+                continue;
+            }
+            next_insn += 1;
+        }
+        if moved.contains(val) {
+            *val -= size as Int;
+        }
+    }
+}
+
+#[derive(Debug,Clone)]
+pub struct FuzzOptions {
+    // Case n uses seed + n.
+    pub seed: u64,
+    pub cases: u64,
+    pub max_insns: usize,
+    pub step_limit: u64,
+}
+
+impl Default for FuzzOptions {
+    fn default() -> FuzzOptions {
+        FuzzOptions { seed: 1, cases: 500, max_insns: 24, step_limit: 5_000 }
+    }
+}
+
+// Runs the cases, stopping at the first disagreement, which is shrunk.
+pub fn fuzz(engines: &[&dyn Engine], options: &FuzzOptions) -> Result<(), Box<Mismatch>> {
+    for seed in options.seed..options.seed + options.cases {
+        let (program, inputs) = generate(seed, options.max_insns);
+        if let Some((other, _, _)) = compare(engines, &program, &inputs, options.step_limit) {
+            let pair = [engines[0], engines[other]];
+            let (program, inputs) = shrink(&program, &inputs, |program, inputs| {
+                compare(&pair, program, inputs, options.step_limit).is_some()
+            });
+            let (_, expected, outcome) = compare(&pair, &program, &inputs, options.step_limit).unwrap();
+            return Err(Box::new(Mismatch {
+                seed,
+                program,
+                inputs,
+                engines: [engines[0].name().into(), engines[other].name().into()],
+                outcomes: [expected, outcome],
+            }));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let (program, inputs) = generate(7, 10);
+        assert_eq!(generate(7, 10), (program.clone(), inputs));
+        assert_ne!(generate(8, 10).0, program);
+        // Instructions decode up to the halt.
+        let mut pc = 0;
+        while program[pc] != 99 {
+            pc += decode(program[pc]).unwrap().size();
+        }
+    }
+
+    #[test]
+    fn test_engines_agree() {
+        let engines = standard_engines();
+        let engines: Vec<&dyn Engine> = engines.iter().map(|e| e.as_ref()).collect();
+        if let Err(mismatch) = fuzz(&engines, &FuzzOptions { cases: 300, ..Default::default() }) {
+            panic!("{}", mismatch);
+        }
+    }
+
+    // Loses the sign of negative outputs.
+    struct Broken;
+
+    impl Engine for Broken {
+        fn name(&self) -> &str {
+            "broken"
+        }
+
+        fn run(&self, program: &[Int], inputs: &[Int], step_limit: u64) -> Outcome {
+            let mut outcome = Interpreter.run(program, inputs, step_limit);
+            outcome.outputs.iter_mut().for_each(|val| *val = val.wrapping_abs());
+            outcome
+        }
+    }
+
+    #[test]
+    fn test_shrink() {
+        let mismatch = fuzz(&[&Interpreter, &Broken], &Default::default()).unwrap_err();
+        assert_eq!(mismatch.engines, ["interpreter", "broken"]);
+        assert_ne!(mismatch.outcomes[0], mismatch.outcomes[1]);
+        assert!(mismatch.program.len() + mismatch.inputs.len() <= 3, "{}", mismatch);
+
+        // Values as far from zero as they go shrink too.
+        let (program, inputs) = shrink(&[99, Int::MIN], &[Int::MIN], |program, inputs| {
+            program.len() == 2 && program[1] < 0 && !inputs.is_empty()
+        });
+        assert_eq!((program, inputs), (vec![0, -1], vec![0]));
+    }
+}
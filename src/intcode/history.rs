@@ -0,0 +1,206 @@
+use std::collections::VecDeque;
+use super::{Int, IntcodeMachine, Input, Output, Word};
+
+// An undo log, so that a machine can step backwards.  Each step records
+// what it changed: the old pc, rel_base and halted flag, the old value of
+// any memory written, and any input read or output written.  Going back
+// puts input back on the input queue, and removes output from the output
+// queue if it's still there (output already taken can't be recalled).
+// Changes made from outside, such as `set`, aren't recorded.
+
+#[derive(Debug,Clone)]
+struct Record<W> {
+    pc: usize,
+    rel_base: W,
+    halted: bool,
+    writes: Vec<(usize, W)>,
+    inputs: Vec<W>,
+    outputs: usize,
+}
+
+#[derive(Debug,Clone)]
+pub struct History<W = Int> {
+    records: VecDeque<Record<W>>,
+    // The most steps kept, if limited
+    limit: Option<usize>,
+    current: Option<Record<W>>,
+}
+
+impl<W: Word> History<W> {
+    fn new(limit: Option<usize>) -> History<W> {
+        History { records: VecDeque::new(), limit, current: None }
+    }
+
+    pub(super) fn begin(&mut self, pc: usize, rel_base: W, halted: bool) {
+        self.current = Some(Record { pc, rel_base, halted, writes: Vec::new(), inputs: Vec::new(), outputs: 0 });
+    }
+
+    pub(super) fn commit(&mut self) {
+        if let Some(record) = self.current.take() {
+            if self.limit == Some(self.records.len()) {
+                self.records.pop_front();
+            }
+            if self.limit != Some(0) {
+                self.records.push_back(record);
+            }
+        }
+    }
+
+    pub(super) fn abort(&mut self) {
+        self.current = None;
+    }
+
+    pub(super) fn note_write(&mut self, addr: usize, old: W) {
+        if let Some(record) = &mut self.current {
+            record.writes.push((addr, old));
+        }
+    }
+
+    pub(super) fn note_input(&mut self, val: W) {
+        if let Some(record) = &mut self.current {
+            record.inputs.push(val);
+        }
+    }
+
+    pub(super) fn note_output(&mut self) {
+        if let Some(record) = &mut self.current {
+            record.outputs += 1;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+impl<I: Input<W>, O: Output<W>, W: Word> IntcodeMachine<I, O, W> {
+    // Start recording steps, keeping at most `limit` of them.
+    pub fn enable_history(&mut self, limit: Option<usize>) {
+        self.history = Some(History::new(limit));
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    pub fn history(&self) -> Option<&History<W>> {
+        self.history.as_ref()
+    }
+
+    // The earliest step number which can be rewound to.
+    pub fn earliest_step(&self) -> Option<u64> {
+        self.history.as_ref().map(|h| self.steps - h.len() as u64)
+    }
+
+    // Undo the last step.  Returns false if there's nothing to undo.
+    pub fn step_back(&mut self) -> bool {
+        let record = match self.history.as_mut().and_then(|h| h.records.pop_back()) {
+            Some(record) => record,
+            None => return false,
+        };
+        for (addr, old) in record.writes.into_iter().rev() {
+            self.data.set(addr, old);
+            if let Some(cache) = &mut self.cache {
+                cache.invalidate(addr);
+            }
+        }
+        for val in record.inputs.into_iter().rev() {
+            self.inputs.unread(val);
+        }
+        for _ in 0..record.outputs {
+            self.outputs.unwrite();
+        }
+        self.pc = record.pc;
+        self.rel_base = record.rel_base;
+        self.halted = record.halted;
+        self.steps -= 1;
+        // Don't stop straight away at a breakpoint here when resuming.
+        self.break_skip = Some(self.pc);
+        self.watch_hit = None;
+        true
+    }
+
+    // Go back to how things were when `steps()` was `step`.  Returns false
+    // (changing nothing) if that isn't in the history.
+    pub fn rewind_to(&mut self, step: u64) -> bool {
+        if self.earliest_step().is_none_or(|earliest| step < earliest) || step > self.steps {
+            return false;
+        }
+        while self.steps > step {
+            self.step_back();
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Event;
+
+    // Reads two numbers and outputs their product and their sum.
+    const PROG: &[Int] = &[3,17, 3,18, 2,17,18,19, 4,19, 1,17,18,19, 4,19, 99];
+
+    #[test]
+    fn test_step_back() {
+        let mut machine = IntcodeMachine::new(PROG);
+        machine.enable_history(None);
+        machine.send_input(6);
+        machine.send_input(7);
+        let start = machine.clone();
+        machine.run_until_output().unwrap();
+        assert_eq!(machine.steps(), 4);
+
+        // Back over the output and multiply to just after the inputs.
+        assert!(machine.step_back());
+        assert!(machine.step_back());
+        assert_eq!(machine.pc(), 4);
+        assert_eq!(machine.get_data(17, 3), vec![6, 7, 0]);
+        assert!(machine.get_inputs().is_empty());
+
+        assert!(machine.rewind_to(0));
+        assert!(!machine.step_back());
+        assert_eq!(machine.get_data(0, 20), start.get_data(0, 20));
+        assert_eq!(machine.get_inputs(), &[6, 7]);
+        assert_eq!(machine.pc(), 0);
+
+        // Running forwards again gives the same results.
+        machine.run_until_halt().unwrap();
+        assert_eq!(machine.get_outputs(), &[42, 13]);
+    }
+
+    #[test]
+    fn test_outputs_and_limit() {
+        let mut machine = IntcodeMachine::new(PROG);
+        machine.enable_history(Some(3));
+        machine.send_input(2);
+        machine.send_input(3);
+        machine.run_until_halt().unwrap();
+        assert_eq!(machine.get_outputs(), &[6, 5]);
+        assert_eq!(machine.earliest_step(), Some(4));
+        assert!(!machine.rewind_to(3));
+        assert!(machine.rewind_to(5));
+        // The last output was undone, and the halt.
+        assert_eq!(machine.get_outputs(), &[6]);
+        assert!(!machine.is_halted());
+        assert_eq!(machine.run_until_event().unwrap(), Event::Output(6));
+        assert_eq!(machine.run_until_event().unwrap(), Event::Output(5));
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let mut machine = IntcodeMachine::new(PROG);
+        machine.enable_history(None);
+        machine.send_input(1);
+        machine.send_input(1);
+        machine.add_breakpoint(8);
+        assert_eq!(machine.run_until_event().unwrap(), Event::Breakpoint(8));
+        machine.step().unwrap();
+        assert!(machine.step_back());
+        assert_eq!(machine.run_until_event().unwrap(), Event::Output(1));
+    }
+}
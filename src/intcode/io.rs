@@ -0,0 +1,297 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{BufRead, Read, Write};
+use std::rc::Rc;
+use std::sync::mpsc::{Receiver, Sender};
+use super::Int;
+
+// Both are generic over the machine's word type, defaulting to Int.
+pub trait Input<W = Int> {
+    // Returns None if no input is available (yet).
+    fn read(&mut self) -> Option<W>;
+
+    // Put back a value read, when stepping backwards.  Sources which
+    // can't do that ignore it.
+    fn unread(&mut self, _val: W) {}
+}
+
+pub trait Output<W = Int> {
+    fn write(&mut self, val: W);
+
+    // Remove the oldest value written, for sinks which keep them.
+    // run_until_event reports values taken this way as Event::Output.
+    fn take(&mut self) -> Option<W> {
+        None
+    }
+
+    // Remove the last value written, if still held, when stepping
+    // backwards.
+    fn unwrite(&mut self) {}
+}
+
+impl<W> Input<W> for VecDeque<W> {
+    fn read(&mut self) -> Option<W> {
+        self.pop_front()
+    }
+
+    fn unread(&mut self, val: W) {
+        self.push_front(val);
+    }
+}
+
+impl<W> Output<W> for VecDeque<W> {
+    fn write(&mut self, val: W) {
+        self.push_back(val);
+    }
+
+    fn take(&mut self) -> Option<W> {
+        self.pop_front()
+    }
+
+    fn unwrite(&mut self) {
+        self.pop_back();
+    }
+}
+
+impl<W, F: FnMut() -> Option<W>> Input<W> for F {
+    fn read(&mut self) -> Option<W> {
+        self()
+    }
+}
+
+impl<W, F: FnMut(W)> Output<W> for F {
+    fn write(&mut self, val: W) {
+        self(val)
+    }
+}
+
+// Blocks until a value arrives; no input once every sender has gone.
+impl<W> Input<W> for Receiver<W> {
+    fn read(&mut self) -> Option<W> {
+        self.recv().ok()
+    }
+}
+
+impl<W> Output<W> for Sender<W> {
+    fn write(&mut self, val: W) {
+        // Nobody left to listen; the value is dropped.
+        let _ = self.send(val);
+    }
+}
+
+// A shared queue for connecting one machine's output to another's input
+// on the same thread.  Clones (including of a machine using one) share
+// the same queue.
+#[derive(Debug,Clone,Default)]
+pub struct Pipe(Rc<RefCell<VecDeque<Int>>>);
+
+impl Pipe {
+    pub fn new() -> Pipe {
+        Default::default()
+    }
+
+    pub fn push(&self, val: Int) {
+        self.0.borrow_mut().push_back(val);
+    }
+
+    pub fn pop(&self) -> Option<Int> {
+        self.0.borrow_mut().pop_front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+}
+
+impl Input for Pipe {
+    fn read(&mut self) -> Option<Int> {
+        self.pop()
+    }
+
+    fn unread(&mut self, val: Int) {
+        self.0.borrow_mut().push_front(val);
+    }
+}
+
+impl Output for Pipe {
+    fn write(&mut self, val: Int) {
+        self.push(val);
+    }
+}
+
+// Reads integers separated by commas and/or whitespace, stopping at the
+// end of input or anything which isn't a number.
+pub struct NumberReader<R> {
+    reader: R,
+    pending: VecDeque<Int>,
+    finished: bool,
+}
+
+impl<R: BufRead> NumberReader<R> {
+    pub fn new(reader: R) -> NumberReader<R> {
+        NumberReader { reader, pending: VecDeque::new(), finished: false }
+    }
+}
+
+impl<R: BufRead> Input for NumberReader<R> {
+    fn read(&mut self) -> Option<Int> {
+        while self.pending.is_empty() && !self.finished {
+            let mut line = String::new();
+            match self.reader.read_line(&mut line) {
+                Ok(0) | Err(_) => self.finished = true,
+                Ok(_) => {
+                    for token in line.split(|c: char| c == ',' || c.is_whitespace())
+                                     .filter(|s| !s.is_empty()) {
+                        match token.parse() {
+                            Ok(val) => self.pending.push_back(val),
+                            Err(_) => {
+                                self.finished = true;
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        self.pending.pop_front()
+    }
+}
+
+// Reads bytes as ASCII codes.
+pub struct AsciiReader<R> {
+    reader: R,
+}
+
+impl<R: Read> AsciiReader<R> {
+    pub fn new(reader: R) -> AsciiReader<R> {
+        AsciiReader { reader }
+    }
+}
+
+impl<R: Read> Input for AsciiReader<R> {
+    fn read(&mut self) -> Option<Int> {
+        let mut buf = [0u8];
+        match self.reader.read(&mut buf) {
+            Ok(1) => Some(buf[0] as Int),
+            _ => None,
+        }
+    }
+}
+
+// Writes each value on its own line.
+pub struct NumberWriter<W> {
+    writer: W,
+}
+
+impl<W: Write> NumberWriter<W> {
+    pub fn new(writer: W) -> NumberWriter<W> {
+        NumberWriter { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Output for NumberWriter<W> {
+    fn write(&mut self, val: Int) {
+        let _ = writeln!(self.writer, "{}", val);
+    }
+}
+
+// Writes values as ASCII characters, flushing at each newline.
+pub struct AsciiWriter<W> {
+    writer: W,
+}
+
+impl<W: Write> AsciiWriter<W> {
+    pub fn new(writer: W) -> AsciiWriter<W> {
+        AsciiWriter { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Output for AsciiWriter<W> {
+    fn write(&mut self, val: Int) {
+        let _ = self.writer.write_all(&[val as u8]);
+        if val == 10 {
+            let _ = self.writer.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{IntcodeMachine, Event};
+    use std::sync::mpsc::channel;
+
+    // Adds one to each input until it reads zero.
+    const INCR: &[Int] = &[3,15, 1006,15,14, 101,1,15,15, 4,15, 1105,1,0, 99, 0];
+
+    #[test]
+    fn test_readers_writers() {
+        let mut input = NumberReader::new(&b"1, 2\n3 -4\n\nx 5"[..]);
+        let vals: Vec<_> = std::iter::from_fn(|| input.read()).collect();
+        assert_eq!(vals, vec![1, 2, 3, -4]);
+
+        let mut input = AsciiReader::new(&b"hi"[..]);
+        assert_eq!((input.read(), input.read(), input.read()), (Some(104), Some(105), None));
+
+        let mut output = NumberWriter::new(Vec::new());
+        output.write(3);
+        output.write(-1);
+        assert_eq!(output.into_inner(), b"3\n-1\n");
+    }
+
+    #[test]
+    fn test_closures() {
+        let mut next = 0;
+        let mut seen = Vec::new();
+        let input = || {
+            next += 1;
+            Some(if next < 4 { next } else { 0 })
+        };
+        let mut machine = IntcodeMachine::with_io(INCR, input, |v| seen.push(v));
+        assert_eq!(machine.run_until_event().unwrap(), Event::Halted);
+        drop(machine);
+        assert_eq!(seen, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn test_pipe() {
+        let pipe = Pipe::new();
+        let mut first = IntcodeMachine::with_io(INCR, VecDeque::new(), pipe.clone());
+        let mut second = IntcodeMachine::with_io(INCR, pipe.clone(), VecDeque::new());
+        for v in &[10, 20, 0] {
+            first.send_input(*v);
+        }
+        first.run_until_halt().unwrap();
+        assert_eq!(pipe.len(), 2);
+        pipe.push(0);
+        second.run_until_halt().unwrap();
+        assert_eq!(second.get_outputs(), &[12, 22]);
+        assert!(pipe.is_empty());
+    }
+
+    #[test]
+    fn test_channels() {
+        let (in_tx, in_rx) = channel();
+        let (out_tx, out_rx) = channel();
+        let handle = std::thread::spawn(move || {
+            IntcodeMachine::with_io(INCR, in_rx, out_tx).run_until_halt()
+        });
+        in_tx.send(41).unwrap();
+        assert_eq!(out_rx.recv().unwrap(), 42);
+        in_tx.send(0).unwrap();
+        handle.join().unwrap().unwrap();
+        assert!(out_rx.recv().is_err());
+    }
+}
@@ -0,0 +1,201 @@
+use std::io;
+use std::fmt::Display;
+use std::path::Path;
+use super::Int;
+
+// Loading programs.  As text, values are separated by commas and/or
+// whitespace, and `#` or `;` starts a comment running to the end of the
+// line:
+//
+//   # Outputs its input
+//   3,0,
+//   4,0, 99   ; done
+//
+// The binary form is the magic bytes "ICP\x01", then each value as a
+// zigzag-encoded LEB128 varint, so that small values take a byte each.
+
+const BINARY_MAGIC: &[u8; 4] = b"ICP\x01";
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    // Line and column count from 1.
+    BadValue { line: usize, column: usize, token: String },
+    EmptyValue { line: usize, column: usize },
+    // Offset in bytes of the bad value
+    Binary { offset: usize, message: String },
+}
+
+impl std::error::Error for LoadError {
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            LoadError::Io(e) => write!(f, "I/O error: {}", e),
+            LoadError::BadValue { line, column, token } => {
+                write!(f, "Bad value {:?} at line {}, column {}", token, line, column)
+            }
+            LoadError::EmptyValue { line, column } => {
+                write!(f, "Missing value before comma at line {}, column {}", line, column)
+            }
+            LoadError::Binary { offset, message } => write!(f, "{} at byte {}", message, offset),
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+pub fn parse_program(text: &str) -> Result<Vec<Int>, LoadError> {
+    let mut result = Vec::new();
+    // Every comma must follow a value, so only one trailing comma is
+    // allowed and none before the first value.
+    let mut after_value = false;
+    for (lineno, line) in text.lines().enumerate() {
+        let line = line.split(['#', ';']).next().unwrap_or("");
+        let mut chars = line.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            let column = line[..start].chars().count() + 1;
+            if c == ',' {
+                if !after_value {
+                    return Err(LoadError::EmptyValue { line: lineno + 1, column });
+                }
+                after_value = false;
+            } else if !c.is_whitespace() {
+                let mut end = start + c.len_utf8();
+                while let Some(&(i, c)) = chars.peek() {
+                    if c == ',' || c.is_whitespace() {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                let token = &line[start..end];
+                let val = token.parse().map_err(|_| {
+                    LoadError::BadValue { line: lineno + 1, column, token: token.into() }
+                })?;
+                result.push(val);
+                after_value = true;
+            }
+        }
+    }
+    Ok(result)
+}
+
+pub fn to_binary(program: &[Int]) -> Vec<u8> {
+    let mut result = BINARY_MAGIC.to_vec();
+    for &val in program {
+        let mut zigzag = ((val as i64) << 1 ^ (val as i64) >> 63) as u64;
+        loop {
+            let byte = (zigzag & 0x7f) as u8;
+            zigzag >>= 7;
+            if zigzag == 0 {
+                result.push(byte);
+                break;
+            }
+            result.push(byte | 0x80);
+        }
+    }
+    result
+}
+
+pub fn is_binary(data: &[u8]) -> bool {
+    data.starts_with(BINARY_MAGIC)
+}
+
+pub fn from_binary(data: &[u8]) -> Result<Vec<Int>, LoadError> {
+    let err = |offset, message: &str| Err(LoadError::Binary { offset, message: message.into() });
+    if !is_binary(data) {
+        return err(0, "Not a binary program");
+    }
+    let mut result = Vec::new();
+    let mut offset = BINARY_MAGIC.len();
+    while offset < data.len() {
+        let start = offset;
+        let mut zigzag: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = match data.get(offset) {
+                Some(&byte) => byte,
+                None => return err(start, "Truncated value"),
+            };
+            offset += 1;
+            if shift >= 64 || (shift == 63 && byte & 0x7e != 0) {
+                return err(start, "Value too large");
+            }
+            zigzag |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let val = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+        match Int::try_from(val) {
+            Ok(val) => result.push(val),
+            Err(_) => return err(start, "Value out of range"),
+        }
+    }
+    Ok(result)
+}
+
+// Either form, going by the magic bytes.
+pub fn load_file(path: impl AsRef<Path>) -> Result<Vec<Int>, LoadError> {
+    let data = std::fs::read(path)?;
+    if is_binary(&data) {
+        from_binary(&data)
+    } else {
+        let text = String::from_utf8(data).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, e)
+        })?;
+        parse_program(&text)
+    }
+}
+
+// The program from a day's input file (see `get_input`).
+pub fn load_day(n: u32) -> Result<Vec<Int>, LoadError> {
+    parse_program(&crate::get_input(n)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "# Outputs its input\n3,0,\n  4,0, 99   ; done\n";
+        assert_eq!(parse_program(text).unwrap(), vec![3, 0, 4, 0, 99]);
+        assert_eq!(parse_program("1,-2\n3 4,\n").unwrap(), vec![1, -2, 3, 4]);
+        assert_eq!(parse_program("").unwrap(), vec![]);
+
+        assert!(matches!(parse_program("1,2,\n3,x5,6"),
+                         Err(LoadError::BadValue { line: 2, column: 3, token }) if token == "x5"));
+        assert!(matches!(parse_program("1,2,\n  ,3"),
+                         Err(LoadError::EmptyValue { line: 2, column: 3 })));
+        assert!(matches!(parse_program(","),
+                         Err(LoadError::EmptyValue { line: 1, column: 1 })));
+        assert!(matches!(parse_program(" ,1,2"),
+                         Err(LoadError::EmptyValue { line: 1, column: 2 })));
+        assert!(matches!(parse_program("1,2,,"),
+                         Err(LoadError::EmptyValue { line: 1, column: 5 })));
+        assert_eq!(parse_program("1;2\n99999999999999999999").unwrap_err().to_string(),
+                   "Bad value \"99999999999999999999\" at line 2, column 1");
+    }
+
+    #[test]
+    fn test_binary() {
+        let program = [1, -1, 0, 63, -64, 64, 1 << 40, Int::MIN, Int::MAX];
+        let data = to_binary(&program);
+        assert_eq!(&data[..8], b"ICP\x01\x02\x01\x00\x7e");
+        assert_eq!(from_binary(&data).unwrap(), program);
+
+        assert!(matches!(from_binary(&data[..data.len() - 1]),
+                         Err(LoadError::Binary { offset, .. }) if offset == data.len() - 10));
+        assert!(matches!(from_binary(b"ICP\x01\x00\xff\xff\xff\xff\xff\xff\xff\xff\xff\x7f"),
+                         Err(LoadError::Binary { offset: 5, .. })));
+        assert!(from_binary(b"1,2,3").is_err());
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use super::{Int, Word};
+
+// Machine memory: a dense vector for low addresses, and beyond
+// `dense_limit` a map of fixed-size pages allocated on first (non-zero)
+// write, so that a write to a huge address doesn't allocate everything
+// below it.  Pages are counted from `dense_limit`, so none overlaps the
+// dense part.  Unwritten memory reads as zero.
+
+pub const PAGE_SIZE: usize = 1024;
+// Default limit of the dense part (in words) unless the program is bigger.
+pub const DENSE_LIMIT: usize = 1 << 20;
+
+#[derive(Debug,Clone,Default)]
+pub struct Memory<W = Int> {
+    dense: Vec<W>,
+    dense_limit: usize,
+    pages: HashMap<usize, Box<[W]>>,
+    len: usize,
+}
+
+impl<W: Word> Memory<W> {
+    pub fn new(data: &[W]) -> Memory<W> {
+        Memory::with_dense_limit(data, DENSE_LIMIT.max(data.len()))
+    }
+
+    // Everything written beyond the initial data goes into pages.
+    pub fn sparse(data: &[W]) -> Memory<W> {
+        Memory::with_dense_limit(data, data.len())
+    }
+
+    pub fn with_dense_limit(data: &[W], dense_limit: usize) -> Memory<W> {
+        let split = data.len().min(dense_limit);
+        let mut memory = Memory {
+            dense: data[..split].to_vec(),
+            dense_limit,
+            pages: HashMap::new(),
+            len: split,
+        };
+        for (addr, val) in data.iter().enumerate().skip(split) {
+            memory.set(addr, val.clone());
+        }
+        memory
+    }
+
+    pub fn get(&self, addr: usize) -> W {
+        if addr < self.dense.len() {
+            self.dense[addr].clone()
+        } else if addr < self.dense_limit {
+            W::default()
+        } else {
+            let (page, offset) = self.page_of(addr);
+            self.pages.get(&page).map_or_else(W::default, |page| page[offset].clone())
+        }
+    }
+
+    pub fn set(&mut self, addr: usize, val: W) {
+        if addr < self.dense_limit {
+            if addr >= self.dense.len() {
+                self.dense.resize(addr + 1, W::default());
+            }
+            self.dense[addr] = val;
+        } else {
+            let (page, offset) = self.page_of(addr);
+            if let Some(page) = self.pages.get_mut(&page) {
+                page[offset] = val;
+            } else if !val.is_zero() {
+                let mut data = vec![W::default(); PAGE_SIZE].into_boxed_slice();
+                data[offset] = val;
+                self.pages.insert(page, data);
+            }
+        }
+        self.len = self.len.max(addr.saturating_add(1));
+    }
+
+    // Page number and offset of an address at or beyond the dense limit
+    fn page_of(&self, addr: usize) -> (usize, usize) {
+        let rel = addr - self.dense_limit;
+        (rel / PAGE_SIZE, rel % PAGE_SIZE)
+    }
+
+    pub fn dense_limit(&self) -> usize {
+        self.dense_limit
+    }
+
+    // One past the highest address loaded or written.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn read(&self, start: usize, len: usize) -> Vec<W> {
+        (start..start.saturating_add(len)).map(|addr| self.get(addr)).collect()
+    }
+
+    pub fn dense(&self) -> &[W] {
+        &self.dense
+    }
+
+    // The allocated pages beyond the dense part, as (start address,
+    // contents), in address order.
+    pub fn pages(&self) -> Vec<(usize, &[W])> {
+        let mut result: Vec<_> = self.pages.iter()
+                                     .map(|(&page, data)| (self.dense_limit + page * PAGE_SIZE, &data[..]))
+                                     .collect();
+        result.sort_unstable_by_key(|&(addr, _)| addr);
+        result
+    }
+}
+
+impl<W: Word> From<&[W]> for Memory<W> {
+    fn from(data: &[W]) -> Memory<W> {
+        Memory::new(data)
+    }
+}
+
+impl<W: Word> From<Vec<W>> for Memory<W> {
+    fn from(data: Vec<W>) -> Memory<W> {
+        Memory::new(&data)
+    }
+}
+
+// Read access to a program's words, whether in a slice or a Memory.
+// Reading past the end gives None.
+pub trait Words {
+    fn word(&self, addr: usize) -> Option<Int>;
+    fn word_count(&self) -> usize;
+
+    // The ranges of addresses, as (start, end), which may hold anything but zero, in order,
+    // so that scans needn't visit memory that was never written.
+    fn loaded(&self) -> Vec<(usize, usize)> {
+        vec![(0, self.word_count())]
+    }
+}
+
+impl Words for [Int] {
+    fn word(&self, addr: usize) -> Option<Int> {
+        self.get(addr).cloned()
+    }
+
+    fn word_count(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<const N: usize> Words for [Int; N] {
+    fn word(&self, addr: usize) -> Option<Int> {
+        self.get(addr).cloned()
+    }
+
+    fn word_count(&self) -> usize {
+        N
+    }
+}
+
+impl Words for Vec<Int> {
+    fn word(&self, addr: usize) -> Option<Int> {
+        self.get(addr).cloned()
+    }
+
+    fn word_count(&self) -> usize {
+        self.len()
+    }
+}
+
+impl Words for Memory {
+    fn word(&self, addr: usize) -> Option<Int> {
+        if addr < self.len {
+            Some(self.get(addr))
+        } else {
+            None
+        }
+    }
+
+    fn word_count(&self) -> usize {
+        self.len
+    }
+
+    fn loaded(&self) -> Vec<(usize, usize)> {
+        let mut result = vec![(0, self.dense.len())];
+        for (start, _) in self.pages() {
+            let end = (start + PAGE_SIZE).min(self.len);
+            match result.last_mut() {
+                Some(last) if last.1 == start => last.1 = end,
+                _ => result.push((start, end)),
+            }
+        }
+        result
+    }
+}
+
+impl<T: Words + ?Sized> Words for &T {
+    fn word(&self, addr: usize) -> Option<Int> {
+        (**self).word(addr)
+    }
+
+    fn word_count(&self) -> usize {
+        (**self).word_count()
+    }
+
+    fn loaded(&self) -> Vec<(usize, usize)> {
+        (**self).loaded()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::IntcodeMachine;
+
+    #[test]
+    fn test_dense_and_sparse() {
+        let mut mem: Memory = Memory::with_dense_limit(&[1, 2, 3], 2);
+        assert_eq!(mem.dense(), &[1, 2]);
+        assert_eq!(mem.read(0, 4), vec![1, 2, 3, 0]);
+        assert_eq!(mem.len(), 3);
+
+        mem.set(1_000_000_000_000, 5);
+        mem.set(2_000_000_000_000, 0);
+        assert_eq!(mem.get(1_000_000_000_000), 5);
+        assert_eq!(mem.get(1_000_000_000_001), 0);
+        assert_eq!(mem.len(), 2_000_000_000_001);
+        assert_eq!(mem.pages().iter().map(|&(addr, _)| addr).collect::<Vec<_>>(),
+                   vec![2, 2 + (1_000_000_000_000 - 2) / PAGE_SIZE * PAGE_SIZE]);
+        assert_eq!(mem.dense().len(), 2);
+    }
+
+    #[test]
+    fn test_huge_address() {
+        // Write to and read back from address 10^12, then output it.
+        let prog = [1101,7,8,1_000_000_000_000, 4,1_000_000_000_000, 99];
+        let mut machine = IntcodeMachine::new(&prog);
+        machine.run_until_halt().unwrap();
+        assert_eq!(machine.get_outputs(), &[15]);
+        assert_eq!(machine.get_data(1_000_000_000_000, 2), vec![15, 0]);
+    }
+}
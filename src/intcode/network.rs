@@ -0,0 +1,279 @@
+use std::collections::VecDeque;
+use super::{Int, IntcodeMachine, Event, Error};
+
+// A set of machines exchanging (addr, x, y) packets.  Each machine is
+// given its address as its first input, and sends packets as three
+// consecutive outputs.  When asked for input a machine receives the x and
+// y of the next packet queued for it, or -1 if there is none.
+//
+// Packets sent to the NAT address (if set) are held there, and when the
+// network goes idle the last one is delivered to machine 0.  Packets for
+// a machine which has halted are dropped.
+
+// Number of consecutive empty polls before a machine counts as idle.
+const IDLE_POLLS: usize = 2;
+// Most instructions a machine runs in one turn, so that one which never
+// reads input can't hold up the rest.
+const TURN_STEPS: u64 = 10_000;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Packet {
+    pub addr: Int,
+    pub x: Int,
+    pub y: Int,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum NetEvent {
+    // A machine sent a packet (to any address, including the NAT)
+    Sent { from: usize, packet: Packet },
+    // The NAT woke up an idle network by sending this packet to machine 0
+    NatDelivered(Packet),
+}
+
+pub struct Network {
+    machines: Vec<IntcodeMachine>,
+    queues: Vec<VecDeque<(Int, Int)>>,
+    partial: Vec<Vec<Int>>,
+    idle_polls: Vec<usize>,
+    nat_addr: Option<Int>,
+    nat_packet: Option<Packet>,
+}
+
+impl Network {
+    pub fn new(program: &[Int], count: usize) -> Network {
+        let machines = (0..count).map(|addr| {
+            let mut machine = IntcodeMachine::new(program);
+            machine.send_input(addr as Int);
+            machine
+        }).collect();
+        Network::from_machines(machines)
+    }
+
+    // Build a network from machines which have already been given any
+    // start-up input.  Each machine's address is its index.
+    pub fn from_machines(machines: Vec<IntcodeMachine>) -> Network {
+        let count = machines.len();
+        Network {
+            machines,
+            queues: vec![VecDeque::new(); count],
+            partial: vec![Vec::new(); count],
+            idle_polls: vec![0; count],
+            nat_addr: None,
+            nat_packet: None,
+        }
+    }
+
+    pub fn set_nat(&mut self, addr: Int) {
+        self.nat_addr = Some(addr);
+    }
+
+    pub fn nat_packet(&self) -> Option<Packet> {
+        self.nat_packet
+    }
+
+    pub fn machine(&self, addr: usize) -> &IntcodeMachine {
+        &self.machines[addr]
+    }
+
+    pub fn send(&mut self, packet: Packet) {
+        if let Some(addr) = usize::try_from(packet.addr).ok().filter(|&a| a < self.machines.len()) {
+            if !self.machines[addr].is_halted() {
+                self.queues[addr].push_back((packet.x, packet.y));
+            }
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.queues.iter().all(|q| q.is_empty())
+            && self.machines.iter().zip(self.idle_polls.iter())
+                   .all(|(m, &polls)| polls >= IDLE_POLLS || m.is_halted())
+    }
+
+    fn route(&mut self, from: usize, packet: Packet, events: &mut Vec<NetEvent>) {
+        events.push(NetEvent::Sent { from, packet });
+        self.idle_polls[from] = 0;
+        if Some(packet.addr) == self.nat_addr {
+            self.nat_packet = Some(packet);
+        } else {
+            self.send(packet);
+        }
+    }
+
+    // Run one machine until it has consumed one lot of input and asks
+    // for more, halts, or has had TURN_STEPS steps.  Any step limit of
+    // the machine's own still applies.
+    fn run_machine(&mut self, idx: usize, events: &mut Vec<NetEvent>) -> Result<(), Error> {
+        let limit = self.machines[idx].step_limit;
+        let turn_end = self.machines[idx].steps().saturating_add(TURN_STEPS);
+        self.machines[idx].step_limit = Some(limit.map_or(turn_end, |l| l.min(turn_end)));
+        let result = self.run_turn(idx, events);
+        self.machines[idx].step_limit = limit;
+        match result {
+            Err(Error::StepLimit { .. }) if limit.is_none_or(|l| self.machines[idx].steps() < l) => Ok(()),
+            result => result,
+        }
+    }
+
+    fn run_turn(&mut self, idx: usize, events: &mut Vec<NetEvent>) -> Result<(), Error> {
+        let mut fed = false;
+        loop {
+            match self.machines[idx].run_until_event()? {
+                Event::Output(val) => {
+                    self.partial[idx].push(val);
+                    if let [addr, x, y] = self.partial[idx][..] {
+                        self.partial[idx].clear();
+                        self.route(idx, Packet { addr, x, y }, events);
+                    }
+                }
+                Event::InputNeeded => {
+                    if fed {
+                        return Ok(());
+                    }
+                    fed = true;
+                    match self.queues[idx].pop_front() {
+                        Some((x, y)) => {
+                            self.machines[idx].send_input(x);
+                            self.machines[idx].send_input(y);
+                            self.idle_polls[idx] = 0;
+                        }
+                        None => {
+                            self.machines[idx].send_input(-1);
+                            self.idle_polls[idx] += 1;
+                        }
+                    }
+                }
+                Event::Halted => {
+                    self.queues[idx].clear();
+                    return Ok(());
+                }
+                Event::Breakpoint(_) | Event::Watch { .. } => {}
+            }
+        }
+    }
+
+    // Give every machine one turn, then wake the network via the NAT if
+    // it has gone idle.  Returns the events which happened.
+    pub fn round(&mut self) -> Result<Vec<NetEvent>, Error> {
+        let mut events = Vec::new();
+        for idx in 0..self.machines.len() {
+            self.run_machine(idx, &mut events)?;
+        }
+        if self.is_idle() && !self.machines.is_empty() {
+            if let Some(packet) = self.nat_packet {
+                let packet = Packet { addr: 0, ..packet };
+                self.send(packet);
+                events.push(NetEvent::NatDelivered(packet));
+                for polls in self.idle_polls.iter_mut() {
+                    *polls = 0;
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    // Run until `stop` returns true for an event, which is returned.
+    // Returns None if every machine halts, or the network is idle with
+    // nothing for the NAT to send.
+    pub fn run_until<F>(&mut self, mut stop: F) -> Result<Option<NetEvent>, Error>
+        where F: FnMut(&NetEvent) -> bool
+    {
+        loop {
+            let events = self.round()?;
+            if let Some(event) = events.into_iter().find(|e| stop(e)) {
+                return Ok(Some(event));
+            }
+            if self.machines.iter().all(|m| m.is_halted()) {
+                return Ok(None);
+            }
+            if self.is_idle() {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::asm::assemble;
+
+    // Machine 0 starts a packet off around the ring 0 -> 1 -> 2 -> 3, each
+    // machine adding one to y, and the last sending it to 255.
+    const RING: &str = r#"
+                in   addr
+                jt   addr, #loop
+                out  #1
+                out  #100
+                out  #0
+        loop:   in   x
+                eq   x, #-1, t
+                jt   t, #loop
+                in   y
+                add  addr, #1, dest
+                eq   dest, #4, t
+                jf   t, #send
+                add  #255, #0, dest
+        send:   out  dest
+                out  x
+                add  y, #1, y
+                out  y
+                jt   #1, #loop
+        addr:   data 0
+        x:      data 0
+        y:      data 0
+        t:      data 0
+        dest:   data 0
+    "#;
+
+    #[test]
+    fn test_routing() {
+        let mut network = Network::new(&assemble(RING).unwrap(), 4);
+        let event = network.run_until(|e| matches!(e, NetEvent::Sent { packet, .. } if packet.addr == 255))
+                           .unwrap();
+        assert_eq!(event, Some(NetEvent::Sent { from: 3, packet: Packet { addr: 255, x: 100, y: 3 } }));
+
+        // Without a NAT the packet goes nowhere and the network stalls.
+        assert_eq!(network.run_until(|_| false).unwrap(), None);
+        assert!(network.is_idle());
+    }
+
+    #[test]
+    fn test_nat() {
+        let mut network = Network::new(&assemble(RING).unwrap(), 4);
+        network.set_nat(255);
+        let mut delivered = Vec::new();
+        network.run_until(|e| {
+            if let NetEvent::NatDelivered(packet) = e {
+                delivered.push(packet.y);
+            }
+            delivered.len() == 3
+        }).unwrap();
+        assert_eq!(delivered, vec![3, 7, 11]);
+        assert_eq!(network.nat_packet(), Some(Packet { addr: 255, x: 100, y: 11 }));
+    }
+
+    #[test]
+    fn test_halted_and_busy() {
+        // Machine 0 sends to machine 1, which has halted, then polls.
+        let sender = IntcodeMachine::new(&assemble(r#"
+                    out  #1
+                    out  #5
+                    out  #6
+            loop:   in   x
+                    jt   #1, #loop
+            x:      data 0
+        "#).unwrap());
+        let mut network = Network::from_machines(vec![sender, IntcodeMachine::new(&[99])]);
+        assert_eq!(network.run_until(|_| false).unwrap(), None);
+        assert!(network.is_idle());
+
+        // A machine which never reads input doesn't stop the others.
+        let spinner = IntcodeMachine::new(&[1105, 1, 0]);
+        let sender = IntcodeMachine::new(&[104, 255, 104, 1, 104, 2, 99]);
+        let mut network = Network::from_machines(vec![spinner, sender]);
+        let event = network.run_until(|e| matches!(e, NetEvent::Sent { .. })).unwrap();
+        assert_eq!(event, Some(NetEvent::Sent { from: 1, packet: Packet { addr: 255, x: 1, y: 2 } }));
+        assert_eq!(network.machine(0).step_limit, None);
+    }
+}
@@ -0,0 +1,396 @@
+use std::collections::VecDeque;
+use std::fmt::Display;
+use regex::Regex;
+use super::{Int, IntcodeMachine, Event, Error};
+
+// Drives an ASCII program in the style of `expect`: wait for some output,
+// then send a line in response.  Everything which passes either way is
+// kept in a transcript, which can be saved as text:
+//
+//   < You are in a room.
+//   < Command?
+//   > north
+//
+// and later replayed against the program as a test.  Output values which
+// aren't ASCII (such as a final answer) are kept separately.
+
+pub enum Pattern {
+    // Appears anywhere in the output
+    Text(String),
+    // A whole line of output
+    Line(String),
+    // Matched against complete lines, or whatever output there is when
+    // the program stops for input or halts.
+    Regex(Regex),
+}
+
+impl From<&str> for Pattern {
+    fn from(s: &str) -> Pattern {
+        Pattern::Text(s.into())
+    }
+}
+
+impl From<Regex> for Pattern {
+    fn from(re: Regex) -> Pattern {
+        Pattern::Regex(re)
+    }
+}
+
+impl Pattern {
+    // Returns the matched range and any capture groups.
+    fn find(&self, text: &str, stopped: bool) -> Option<(usize, usize, Vec<Option<String>>)> {
+        match self {
+            Pattern::Text(s) => text.find(&s[..]).map(|start| (start, start + s.len(), Vec::new())),
+            Pattern::Line(s) => {
+                let mut start = 0;
+                for line in text.split_inclusive('\n') {
+                    if line.strip_suffix('\n') == Some(&s[..]) {
+                        return Some((start, start + line.len(), Vec::new()));
+                    }
+                    start += line.len();
+                }
+                None
+            }
+            Pattern::Regex(re) => {
+                if !stopped && !text.ends_with('\n') {
+                    return None;
+                }
+                let caps = re.captures(text)?;
+                let whole = caps.get(0)?;
+                let groups = caps.iter().skip(1).map(|g| g.map(|m| m.as_str().to_string())).collect();
+                Some((whole.start(), whole.end(), groups))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Machine(Error),
+    // The program halted, or wanted input, before the expected output
+    // appeared.  Holds the output since the last match.
+    Halted(String),
+    InputNeeded(String),
+    // Replayed output differed from the transcript
+    Mismatch { expected: String, found: String },
+    Format(String),
+}
+
+impl std::error::Error for ScriptError {
+}
+
+impl Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            ScriptError::Machine(e) => write!(f, "Machine error: {}", e),
+            ScriptError::Halted(out) => write!(f, "Halted before expected output; got {:?}", out),
+            ScriptError::InputNeeded(out) => write!(f, "Input needed before expected output; got {:?}", out),
+            ScriptError::Mismatch { expected, found } => {
+                write!(f, "Expected output {:?}, found {:?}", expected, found)
+            }
+            ScriptError::Format(s) => write!(f, "Bad transcript: {}", s),
+        }
+    }
+}
+
+impl From<Error> for ScriptError {
+    fn from(e: Error) -> Self {
+        ScriptError::Machine(e)
+    }
+}
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum Entry {
+    Output(String),
+    Input(String),
+}
+
+#[derive(Debug,Clone,Default,PartialEq,Eq)]
+pub struct Transcript {
+    entries: Vec<Entry>,
+}
+
+// Compare output by lines, ignoring trailing whitespace, since the text
+// form doesn't keep it.
+fn output_lines(text: &str) -> Vec<&str> {
+    text.lines().map(str::trim_end).collect()
+}
+
+impl Transcript {
+    pub fn new() -> Transcript {
+        Default::default()
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    fn push_output(&mut self, c: char) {
+        match self.entries.last_mut() {
+            Some(Entry::Output(text)) => text.push(c),
+            _ => self.entries.push(Entry::Output(c.to_string())),
+        }
+    }
+
+    fn push_input(&mut self, line: &str) {
+        self.entries.push(Entry::Input(line.into()));
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut result = String::new();
+        for entry in &self.entries {
+            match entry {
+                Entry::Output(text) => {
+                    for line in text.lines() {
+                        result += format!("< {}", line).trim_end();
+                        result.push('\n');
+                    }
+                }
+                Entry::Input(line) => {
+                    result += &format!("> {}\n", line);
+                }
+            }
+        }
+        result
+    }
+
+    pub fn from_text(text: &str) -> Result<Transcript, ScriptError> {
+        let mut transcript = Transcript::new();
+        for line in text.lines() {
+            if let Some(input) = line.strip_prefix("> ") {
+                transcript.push_input(input);
+            } else if let Some(output) = line.strip_prefix("< ").or(if line == "<" { Some("") } else { None }) {
+                for c in output.chars().chain(Some('\n')) {
+                    transcript.push_output(c);
+                }
+            } else {
+                return Err(ScriptError::Format(format!("Bad line {:?}", line)));
+            }
+        }
+        Ok(transcript)
+    }
+
+    // Run the machine, sending the recorded input and checking that the
+    // output matches.  Returns the script so that the final state and any
+    // non-ASCII output can be checked.
+    pub fn replay(&self, machine: IntcodeMachine) -> Result<Script, ScriptError> {
+        let mut script = Script::new(machine);
+        let mut expected = String::new();
+        for entry in &self.entries {
+            match entry {
+                Entry::Output(text) => expected += text,
+                Entry::Input(line) => {
+                    script.check_output(&expected)?;
+                    expected.clear();
+                    script.send_line(line);
+                }
+            }
+        }
+        script.check_output(&expected)?;
+        Ok(script)
+    }
+}
+
+pub struct Match {
+    // Output before the match
+    pub before: String,
+    pub text: String,
+    pub groups: Vec<Option<String>>,
+}
+
+pub struct Script {
+    machine: IntcodeMachine,
+    // Output not yet consumed by a match
+    buffer: String,
+    transcript: Transcript,
+    // Lines sent which the program hasn't started reading.  They go into
+    // the transcript when it does, so that it shows the order in which
+    // the program saw things.
+    unread: VecDeque<String>,
+    values: Vec<Int>,
+}
+
+impl Script {
+    pub fn new(machine: IntcodeMachine) -> Script {
+        Script {
+            machine,
+            buffer: String::new(),
+            transcript: Transcript::new(),
+            unread: VecDeque::new(),
+            values: Vec::new(),
+        }
+    }
+
+    pub fn machine(&self) -> &IntcodeMachine {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut IntcodeMachine {
+        &mut self.machine
+    }
+
+    // Includes any lines sent which haven't been read yet.
+    pub fn transcript(&self) -> Transcript {
+        let mut transcript = self.transcript.clone();
+        for line in &self.unread {
+            transcript.push_input(line);
+        }
+        transcript
+    }
+
+    fn note_reads(&mut self) {
+        let queued = self.machine.input_mut().len();
+        let mut unread: usize = self.unread.iter().map(|l| l.chars().count() + 1).sum();
+        while unread > queued {
+            let line = self.unread.pop_front().unwrap_or_default();
+            unread -= line.chars().count() + 1;
+            self.transcript.push_input(&line);
+        }
+    }
+
+    // Output values outside the ASCII range, in order.
+    pub fn values(&self) -> &[Int] {
+        &self.values
+    }
+
+    // Run until the next output (returned as a char if ASCII), or until
+    // the program stops for input or halts.
+    fn run_to_output(&mut self) -> Result<Option<Event>, ScriptError> {
+        loop {
+            let event = self.machine.run_until_event()?;
+            self.note_reads();
+            match event {
+                Event::Output(val) if (0..128).contains(&val) => {
+                    let c = val as u8 as char;
+                    self.buffer.push(c);
+                    self.transcript.push_output(c);
+                    return Ok(None);
+                }
+                Event::Output(val) => self.values.push(val),
+                Event::Breakpoint(_) | Event::Watch { .. } => {}
+                event => return Ok(Some(event)),
+            }
+        }
+    }
+
+    // Run until the output matches the pattern.  The output up to the
+    // end of the match is consumed.
+    pub fn expect(&mut self, pattern: impl Into<Pattern>) -> Result<Match, ScriptError> {
+        let pattern = pattern.into();
+        let mut stopped = None;
+        loop {
+            if let Some((start, end, groups)) = pattern.find(&self.buffer, stopped.is_some()) {
+                let text = self.buffer[start..end].to_string();
+                let before = self.buffer[..start].to_string();
+                self.buffer.drain(..end);
+                return Ok(Match { before, text, groups });
+            }
+            match stopped {
+                Some(Event::Halted) => return Err(ScriptError::Halted(self.buffer.clone())),
+                Some(_) => return Err(ScriptError::InputNeeded(self.buffer.clone())),
+                None => stopped = self.run_to_output()?,
+            }
+        }
+    }
+
+    pub fn send_line(&mut self, line: &str) {
+        self.unread.push_back(line.into());
+        self.machine.send_input_ascii(line);
+        self.machine.send_input(10);
+    }
+
+    // Wait for the pattern, then send the line.
+    pub fn respond(&mut self, pattern: impl Into<Pattern>, line: &str) -> Result<Match, ScriptError> {
+        let result = self.expect(pattern)?;
+        self.send_line(line);
+        Ok(result)
+    }
+
+    // Run until the program needs input or halts, returning (and
+    // consuming) all the output not yet matched.
+    pub fn read_all(&mut self) -> Result<String, ScriptError> {
+        while self.run_to_output()?.is_none() {}
+        Ok(std::mem::take(&mut self.buffer))
+    }
+
+    fn check_output(&mut self, expected: &str) -> Result<(), ScriptError> {
+        let found = self.read_all()?;
+        if output_lines(&found) != output_lines(expected) {
+            return Err(ScriptError::Mismatch { expected: expected.into(), found });
+        }
+        Ok(())
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.machine.is_halted()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::asm::assemble;
+
+    // Asks where to go until told "go", then outputs 1000.
+    const ADVENTURE: &str = r#"
+                arb  #prompt
+        ask:    out  @0
+                arb  #1
+                jt   @0, #ask
+                arb  #-11           ; back to the start of the prompt
+                add  #0, #0, len
+        read:   in   char
+                eq   char, #10, t
+                jt   t, #check
+                add  len, #1, len
+                jt   #1, #read
+        check:  eq   len, #2, t     ; only "go" is accepted
+                jf   t, #ask
+                out  #1000
+                hlt
+        len:    data 0
+        char:   data 0
+        t:      data 0
+        prompt: data "Where now?\n", 0
+    "#;
+
+    fn adventure() -> IntcodeMachine {
+        IntcodeMachine::new(&assemble(ADVENTURE).unwrap())
+    }
+
+    #[test]
+    fn test_expect() {
+        let mut script = Script::new(adventure());
+        let m = script.respond("now?", "north").unwrap();
+        assert_eq!(m.before, "Where ");
+        script.expect(Pattern::Line("Where now?".into())).unwrap();
+        script.send_line("go");
+        assert!(matches!(script.expect("now?"), Err(ScriptError::Halted(_))));
+        assert_eq!(script.values(), &[1000]);
+
+        let mut script = Script::new(adventure());
+        let m = script.expect(Regex::new(r"(\w+) now").unwrap()).unwrap();
+        assert_eq!(m.groups, vec![Some("Where".to_string())]);
+        assert!(matches!(script.expect("nowhere"), Err(ScriptError::InputNeeded(s)) if s == "?\n"));
+    }
+
+    #[test]
+    fn test_transcript() {
+        let mut script = Script::new(adventure());
+        script.respond("?", "north").unwrap();
+        script.respond("?", "go").unwrap();
+        script.read_all().unwrap();
+        let text = script.transcript().to_text();
+        assert_eq!(text, "< Where now?\n> north\n< Where now?\n> go\n");
+
+        let transcript = Transcript::from_text(&text).unwrap();
+        assert_eq!(transcript, script.transcript());
+        let replayed = transcript.replay(adventure()).unwrap();
+        assert!(replayed.is_halted());
+        assert_eq!(replayed.values(), &[1000]);
+
+        let wrong = Transcript::from_text("< Where now?\n> go\n< Where now?\n").unwrap();
+        assert!(matches!(wrong.replay(adventure()), Err(ScriptError::Mismatch { .. })));
+        assert!(Transcript::from_text("north").is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use std::fmt::Display;
+use std::ops::{Range, RangeInclusive};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use super::{Int, IntcodeMachine};
+
+// Brute force search for patches to a program: every combination of
+// values for some memory cells is tried (like `run_with_input` does for
+// one noun and verb), looking for those which leave the target value at
+// the result address.  Candidates are shared out between threads, each
+// with a step limit so that ones which loop forever are given up on.
+// Candidates which fault or hit the limit just don't match.
+
+#[derive(Debug,Clone)]
+pub struct SearchOptions {
+    // Where the result is read from once the program halts
+    pub result_addr: usize,
+    pub step_limit: Option<u64>,
+    pub threads: usize,
+}
+
+impl Default for SearchOptions {
+    fn default() -> SearchOptions {
+        SearchOptions {
+            result_addr: 0,
+            step_limit: Some(1_000_000),
+            threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+}
+
+// The number of candidates doesn't fit in a usize.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct TooManyCandidates;
+
+impl Display for TooManyCandidates {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "Too many candidates to search")
+    }
+}
+
+impl std::error::Error for TooManyCandidates {
+}
+
+// How many candidates each thread takes at a time
+const CHUNK: usize = 64;
+
+fn range_len(range: &RangeInclusive<Int>) -> Option<usize> {
+    if range.is_empty() {
+        Some(0)
+    } else {
+        let len = (*range.end() as i128 - *range.start() as i128) as u128 + 1;
+        usize::try_from(len).ok()
+    }
+}
+
+fn candidate_count(patches: &[(usize, RangeInclusive<Int>)]) -> Option<usize> {
+    patches.iter().try_fold(1usize, |total, (_, range)| total.checked_mul(range_len(range)?))
+}
+
+// The values for candidate `index`, with the last cell varying fastest.
+// Only called once the ranges are known to fit.
+fn candidate(patches: &[(usize, RangeInclusive<Int>)], mut index: usize) -> Vec<Int> {
+    let mut values = vec![0; patches.len()];
+    for (val, (_, range)) in values.iter_mut().zip(patches).rev() {
+        let len = range_len(range).unwrap_or(usize::MAX);
+        *val = (*range.start() as i128 + (index % len) as i128) as Int;
+        index /= len;
+    }
+    values
+}
+
+// The next chunk of candidates for a thread to try, if any are left.
+// The counter stops at the end rather than wrapping.
+fn claim(next: &AtomicUsize, total: usize) -> Option<Range<usize>> {
+    let start = next.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |start| {
+        (start < total).then(|| start.saturating_add(CHUNK))
+    }).ok()?;
+    Some(start..total.min(start.saturating_add(CHUNK)))
+}
+
+fn try_candidate(base: &IntcodeMachine, patches: &[(usize, RangeInclusive<Int>)],
+                 values: &[Int], target: Int, options: &SearchOptions) -> bool {
+    let mut machine = base.clone();
+    for ((addr, _), &val) in patches.iter().zip(values) {
+        if machine.store(*addr, val).is_err() {
+            return false;
+        }
+    }
+    machine.set_step_limit(options.step_limit);
+    machine.run_until_halt().is_ok() && machine.get_u(options.result_addr) == Ok(target)
+}
+
+// Returns the values of every combination which gives `target`, in the
+// order they'd be tried with the last cell varying fastest.
+pub fn search_patches(program: &[Int], patches: &[(usize, RangeInclusive<Int>)], target: Int,
+                      options: &SearchOptions) -> Result<Vec<Vec<Int>>, TooManyCandidates> {
+    let total = candidate_count(patches).ok_or(TooManyCandidates)?;
+    let base = IntcodeMachine::new(program);
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, Vec<Int>)> = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..options.threads.max(1)).map(|_| s.spawn(|| {
+            let mut found = Vec::new();
+            while let Some(chunk) = claim(&next, total) {
+                for index in chunk {
+                    let values = candidate(patches, index);
+                    if try_candidate(&base, patches, &values, target, options) {
+                        found.push((index, values));
+                    }
+                }
+            }
+            found
+        })).collect();
+        handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+    });
+    results.sort_unstable_by_key(|&(index, _)| index);
+    Ok(results.into_iter().map(|(_, values)| values).collect())
+}
+
+// The usual search: nouns and verbs from 0 to 99 in addresses 1 and 2.
+pub fn search_noun_verb(program: &[Int], target: Int) -> Vec<(Int, Int)> {
+    search_patches(program, &[(1, 0..=99), (2, 0..=99)], target, &Default::default())
+        .expect("10,000 candidates")
+        .into_iter()
+        .map(|values| (values[0], values[1]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::run_with_input;
+
+    // [0] = [noun] * [verb] + 7, where addresses 9 to 13 hold 0 to 4.
+    const PROGRAM: &[Int] = &[2,0,0,0, 1001,0,7,0, 99, 0,1,2,3,4];
+
+    #[test]
+    fn test_search() {
+        assert_eq!(run_with_input(PROGRAM, 11, 12), Ok(2 * 3 + 7));
+        let options = SearchOptions { threads: 3, ..Default::default() };
+        let found = search_patches(PROGRAM, &[(1, 9..=13), (2, 9..=13)], 13, &options);
+        assert_eq!(found, Ok(vec![vec![11, 12], vec![12, 11]]));
+        let empty = RangeInclusive::new(5, 4);
+        assert_eq!(search_patches(PROGRAM, &[(1, 9..=13), (2, empty)], 13, &options), Ok(vec![]));
+
+        let pairs = search_noun_verb(PROGRAM, 19);
+        assert!(pairs.contains(&(12, 13)) && pairs.contains(&(13, 12)));
+        assert!(pairs.iter().all(|&(noun, verb)| run_with_input(PROGRAM, noun, verb) == Ok(19)));
+    }
+
+    #[test]
+    fn test_step_limit() {
+        // Patching 1 into [5] makes the jt at 4 loop forever.
+        let program = [1101,0,0,0, 1105,0,4, 99];
+        let options = SearchOptions { step_limit: Some(100), ..Default::default() };
+        let found = search_patches(&program, &[(1, 1..=3), (5, 0..=1)], 2, &options);
+        assert_eq!(found, Ok(vec![vec![2, 0]]));
+    }
+
+    #[test]
+    fn test_too_many() {
+        let options = SearchOptions { threads: 1, ..Default::default() };
+        assert_eq!(search_patches(PROGRAM, &[(1, Int::MIN..=Int::MAX)], 0, &options),
+                   Err(TooManyCandidates));
+        let wide = [(1, 0..=Int::MAX), (2, 0..=Int::MAX), (3, 0..=1)];
+        assert_eq!(search_patches(PROGRAM, &wide, 0, &options), Err(TooManyCandidates));
+        // The values are still right at the ends of a range almost that big.
+        let range = Int::MIN..=Int::MAX - 1;
+        assert_eq!(candidate(&[(1, range.clone())], 0), vec![Int::MIN]);
+        assert_eq!(candidate(&[(1, range)], usize::MAX - 1), vec![Int::MAX - 1]);
+
+        // The last chunks of that many
+        let next = AtomicUsize::new(usize::MAX - CHUNK - 1);
+        assert_eq!(claim(&next, usize::MAX), Some(usize::MAX - CHUNK - 1..usize::MAX - 1));
+        assert_eq!(claim(&next, usize::MAX), Some(usize::MAX - 1..usize::MAX));
+        assert_eq!(claim(&next, usize::MAX), None);
+        assert_eq!(next.load(Ordering::Relaxed), usize::MAX);
+    }
+}
@@ -0,0 +1,364 @@
+use std::io::{self, Read, Write};
+use std::fmt::Display;
+use super::{Int, IntcodeMachine, Memory};
+use super::memory::DENSE_LIMIT;
+
+// Full machine state can be saved either as text:
+//
+//   intcode-state 2
+//   pc 4
+//   rel_base 0
+//   halted 0
+//   dense_limit 1048576
+//   inputs 1,2
+//   outputs
+//   data 1002,4,3,4,33
+//   page 1048576 0,0,7,...
+//
+// with a `page` line giving the address and contents of each page of
+// sparse memory, or in a binary form: the magic bytes "ICM\x02", then pc,
+// rel_base, halted and the dense limit, then inputs, outputs and data
+// each as a count followed by the values, then the number of pages
+// followed by each page's address and values (as a count and values
+// again).  All numbers in the binary form are little-endian i64.
+//
+// Version 1 didn't record the dense limit, and could restore pages over
+// the dense part, so it isn't read.
+
+const TEXT_HEADER: &str = "intcode-state 2";
+const BINARY_MAGIC: &[u8; 4] = b"ICM\x02";
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Format(String),
+}
+
+impl std::error::Error for SnapshotError {
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            SnapshotError::Io(e) => write!(f, "I/O error: {}", e),
+            SnapshotError::Format(s) => write!(f, "Bad snapshot: {}", s),
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+fn format_err<T>(msg: impl Into<String>) -> Result<T, SnapshotError> {
+    Err(SnapshotError::Format(msg.into()))
+}
+
+fn join<'a>(vals: impl IntoIterator<Item=&'a Int>) -> String {
+    vals.into_iter().map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+}
+
+fn parse_list(s: &str) -> Result<Vec<Int>, SnapshotError> {
+    if s.is_empty() {
+        return Ok(Vec::new());
+    }
+    s.split(',')
+     .map(|v| v.trim().parse().or_else(|_| format_err(format!("Bad value {:?}", v))))
+     .collect()
+}
+
+fn write_i64<W: Write>(w: &mut W, val: i64) -> io::Result<()> {
+    w.write_all(&val.to_le_bytes())
+}
+
+fn read_i64<R: Read>(r: &mut R) -> io::Result<i64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(i64::from_le_bytes(buf))
+}
+
+fn write_list<'a, W, V>(w: &mut W, vals: V) -> io::Result<()>
+    where W: Write, V: IntoIterator<Item=&'a Int>, V::IntoIter: ExactSizeIterator
+{
+    let vals = vals.into_iter();
+    write_i64(w, vals.len() as i64)?;
+    for &val in vals {
+        write_i64(w, val as i64)?;
+    }
+    Ok(())
+}
+
+fn read_list<R: Read>(r: &mut R) -> Result<Vec<Int>, SnapshotError> {
+    let len = read_i64(r)?;
+    if len < 0 {
+        return format_err("Negative length");
+    }
+    let mut result = Vec::new();
+    for _ in 0..len {
+        result.push(to_int(read_i64(r)?)?);
+    }
+    Ok(result)
+}
+
+fn to_int(val: i64) -> Result<Int, SnapshotError> {
+    Int::try_from(val).or_else(|_| format_err(format!("Value {} out of range", val)))
+}
+
+// Anything below the dense limit is already in the data, so is skipped.
+// The saved dense part and limit.  The limit can be no more than a
+// machine would choose for itself, so that a snapshot can't have the
+// dense part grow without bound.
+fn dense_memory(data: &[Int], dense_limit: usize) -> Result<Memory, SnapshotError> {
+    if dense_limit > DENSE_LIMIT.max(data.len()) {
+        return format_err("Bad dense_limit");
+    }
+    Ok(Memory::with_dense_limit(data, dense_limit))
+}
+
+fn set_page(memory: &mut Memory, addr: usize, vals: &[Int]) -> Result<(), SnapshotError> {
+    if addr.checked_add(vals.len()).is_none() {
+        return format_err("Page out of range");
+    }
+    for (i, &val) in vals.iter().enumerate() {
+        if addr + i >= memory.dense_limit() {
+            memory.set(addr + i, val);
+        }
+    }
+    Ok(())
+}
+
+fn restore(data: Memory, pc: usize, rel_base: Int, halted: bool,
+           inputs: Vec<Int>, outputs: Vec<Int>) -> IntcodeMachine {
+    let mut machine = IntcodeMachine::new(&[]);
+    machine.data = data;
+    machine.pc = pc;
+    machine.rel_base = rel_base;
+    machine.halted = halted;
+    machine.inputs = inputs.into();
+    machine.outputs = outputs.into();
+    machine
+}
+
+impl IntcodeMachine {
+    pub fn to_text(&self) -> String {
+        let mut result = format!("{}\npc {}\nrel_base {}\nhalted {}\ndense_limit {}\ninputs {}\noutputs {}\ndata {}\n",
+                                 TEXT_HEADER, self.pc, self.rel_base, self.halted as u8,
+                                 self.data.dense_limit(), join(&self.inputs), join(&self.outputs),
+                                 join(self.data.dense()));
+        for (addr, page) in self.data.pages() {
+            result += &format!("page {} {}\n", addr, join(page));
+        }
+        result
+    }
+
+    pub fn from_text(text: &str) -> Result<IntcodeMachine, SnapshotError> {
+        let mut lines = text.lines();
+        if lines.next().map(str::trim) != Some(TEXT_HEADER) {
+            return format_err("Missing header");
+        }
+        let mut field = |name: &str| -> Result<String, SnapshotError> {
+            let line = lines.next().unwrap_or("");
+            match line.split_once(' ') {
+                Some((key, val)) if key == name => Ok(val.trim().into()),
+                None if line.trim() == name => Ok(String::new()),
+                _ => format_err(format!("Expected {}, found {:?}", name, line)),
+            }
+        };
+        let pc = field("pc")?.parse().or_else(|_| format_err("Bad pc"))?;
+        let rel_base = field("rel_base")?.parse().or_else(|_| format_err("Bad rel_base"))?;
+        let halted = match &field("halted")?[..] {
+            "0" => false,
+            "1" => true,
+            _ => return format_err("Bad halted flag"),
+        };
+        let dense_limit = field("dense_limit")?.parse().or_else(|_| format_err("Bad dense_limit"))?;
+        let inputs = parse_list(&field("inputs")?)?;
+        let outputs = parse_list(&field("outputs")?)?;
+        let mut data = dense_memory(&parse_list(&field("data")?)?, dense_limit)?;
+        for line in lines.filter(|l| !l.trim().is_empty()) {
+            let page = line.strip_prefix("page ")
+                           .and_then(|rest| rest.split_once(' '))
+                           .and_then(|(addr, vals)| Some((addr.parse::<usize>().ok()?, vals)));
+            let (addr, vals) = match page {
+                Some(page) => page,
+                None => return format_err(format!("Bad page line {:?}", line)),
+            };
+            set_page(&mut data, addr, &parse_list(vals)?)?;
+        }
+        Ok(restore(data, pc, rel_base, halted, inputs, outputs))
+    }
+
+    pub fn write_binary<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(BINARY_MAGIC)?;
+        write_i64(w, self.pc as i64)?;
+        write_i64(w, self.rel_base as i64)?;
+        w.write_all(&[self.halted as u8])?;
+        write_i64(w, self.data.dense_limit() as i64)?;
+        write_list(w, &self.inputs)?;
+        write_list(w, &self.outputs)?;
+        write_list(w, self.data.dense())?;
+        let pages = self.data.pages();
+        write_i64(w, pages.len() as i64)?;
+        for (addr, page) in pages {
+            write_i64(w, addr as i64)?;
+            write_list(w, page)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_binary<R: Read>(r: &mut R) -> Result<IntcodeMachine, SnapshotError> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != BINARY_MAGIC {
+            return format_err("Bad magic");
+        }
+        let pc = usize::try_from(read_i64(r)?).or_else(|_| format_err("Bad pc"))?;
+        let rel_base = to_int(read_i64(r)?)?;
+        let mut halted = [0u8];
+        r.read_exact(&mut halted)?;
+        let halted = match halted[0] {
+            0 => false,
+            1 => true,
+            _ => return format_err("Bad halted flag"),
+        };
+        let dense_limit = usize::try_from(read_i64(r)?).or_else(|_| format_err("Bad dense_limit"))?;
+        let inputs = read_list(r)?;
+        let outputs = read_list(r)?;
+        let mut data = dense_memory(&read_list(r)?, dense_limit)?;
+        let pages = read_i64(r)?;
+        for _ in 0..pages {
+            let addr = usize::try_from(read_i64(r)?).or_else(|_| format_err("Bad page address"))?;
+            set_page(&mut data, addr, &read_list(r)?)?;
+        }
+        Ok(restore(data, pc, rel_base, halted, inputs, outputs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Event;
+    use super::super::memory::PAGE_SIZE;
+
+    // Reads two inputs and outputs their sum, then their product.
+    const PROG: &[Int] = &[3,17, 3,18, 1,17,18,19, 4,19, 2,17,18,19, 4,19, 99];
+
+    #[test]
+    fn test_fork() {
+        let mut machine = IntcodeMachine::new(PROG);
+        machine.send_input(6);
+        assert!(matches!(machine.run_until_event().unwrap(), Event::InputNeeded));
+
+        let mut fork = machine.clone();
+        machine.send_input(7);
+        fork.send_input(8);
+        assert!(matches!(machine.run_until_event().unwrap(), Event::Output(13)));
+        assert!(matches!(fork.run_until_event().unwrap(), Event::Output(14)));
+    }
+
+    #[test]
+    fn test_text() {
+        let mut machine = IntcodeMachine::new(PROG);
+        machine.send_input(2);
+        machine.send_input(3);
+        for _ in 0..4 {
+            machine.step().unwrap();
+        }
+        let text = machine.to_text();
+        assert_eq!(text.lines().nth(1), Some("pc 10"));
+        assert_eq!(text.lines().nth(6), Some("outputs 5"));
+
+        let mut restored = IntcodeMachine::from_text(&text).unwrap();
+        assert_eq!(restored.to_text(), text);
+        restored.run_until_halt().unwrap();
+        assert_eq!(restored.get_outputs(), &[5, 6]);
+    }
+
+    #[test]
+    fn test_binary() {
+        let mut machine = IntcodeMachine::new(PROG);
+        machine.send_input(4);
+        machine.step().unwrap();
+        let mut bytes = Vec::new();
+        machine.write_binary(&mut bytes).unwrap();
+        let mut restored = IntcodeMachine::read_binary(&mut &bytes[..]).unwrap();
+        assert_eq!(restored.to_text(), machine.to_text());
+
+        restored.send_input(5);
+        restored.run_until_halt().unwrap();
+        assert_eq!(restored.get_outputs(), &[9, 20]);
+    }
+
+    #[test]
+    fn test_sparse() {
+        let mut machine = IntcodeMachine::new(&[1101,7,8,1_000_000_000_000,99]);
+        machine.run_until_halt().unwrap();
+        let text = machine.to_text();
+        assert!(text.contains(&format!("\npage {} ", 1_000_000_000_000 / PAGE_SIZE * PAGE_SIZE)));
+        let restored = IntcodeMachine::from_text(&text).unwrap();
+        assert_eq!(restored.get_u(1_000_000_000_000).unwrap(), 15);
+        assert_eq!(restored.to_text(), text);
+
+        let mut bytes = Vec::new();
+        machine.write_binary(&mut bytes).unwrap();
+        let restored = IntcodeMachine::read_binary(&mut &bytes[..]).unwrap();
+        assert_eq!(restored.to_text(), text);
+    }
+
+    // Restores each way, checking both give back the same state.
+    fn round_trip(machine: &IntcodeMachine) -> IntcodeMachine {
+        let text = machine.to_text();
+        let mut bytes = Vec::new();
+        machine.write_binary(&mut bytes).unwrap();
+        let restored = IntcodeMachine::read_binary(&mut &bytes[..]).unwrap();
+        assert_eq!(restored.to_text(), text);
+        let restored = IntcodeMachine::from_text(&text).unwrap();
+        assert_eq!(restored.to_text(), text);
+        restored
+    }
+
+    #[test]
+    fn test_dense_limits() {
+        let prog = [1101,2,3,10,99];
+        let mut machine = IntcodeMachine::from_memory(Memory::sparse(&prog));
+        machine.run_until_halt().unwrap();
+        let restored = round_trip(&machine);
+        assert_eq!(restored.get_data(0, 11), machine.get_data(0, 11));
+        assert_eq!(restored.get(0), Ok(1101));
+        assert_eq!(restored.get(10), Ok(5));
+        assert_eq!(restored.memory().dense_limit(), 5);
+
+        // Everything in pages, and a write into the first of them
+        let mut machine = IntcodeMachine::from_memory(Memory::with_dense_limit(&prog, 0));
+        machine.run_until_halt().unwrap();
+        assert_eq!(machine.memory().pages().len(), 1);
+        let restored = round_trip(&machine);
+        assert_eq!(restored.get_data(0, 11), vec![1101, 2, 3, 10, 99, 0, 0, 0, 0, 0, 5]);
+    }
+
+    #[test]
+    fn test_bad_snapshots() {
+        assert!(IntcodeMachine::from_text("").is_err());
+        assert!(IntcodeMachine::from_text("intcode-state 2\npc x\n").is_err());
+        assert!(IntcodeMachine::from_text("intcode-state 1\npc 0\nrel_base 0\nhalted 0\n\
+                                           inputs\noutputs\ndata 99\n").is_err());
+        assert!(matches!(IntcodeMachine::read_binary(&mut &b"ICM"[..]),
+                         Err(SnapshotError::Io(_))));
+        assert!(matches!(IntcodeMachine::read_binary(&mut &b"XXXX"[..]),
+                         Err(SnapshotError::Format(_))));
+
+        // A dense limit bigger than any machine would have
+        let text = IntcodeMachine::new(&[99]).to_text();
+        let huge = text.replace(&format!("dense_limit {}", DENSE_LIMIT), "dense_limit 1099511627776");
+        assert_ne!(huge, text);
+        assert!(matches!(IntcodeMachine::from_text(&huge),
+                         Err(SnapshotError::Format(e)) if e == "Bad dense_limit"));
+        let mut binary = Vec::new();
+        IntcodeMachine::new(&[99]).write_binary(&mut binary).unwrap();
+        binary[21..29].copy_from_slice(&(1i64 << 40).to_le_bytes());
+        assert!(matches!(IntcodeMachine::read_binary(&mut &binary[..]),
+                         Err(SnapshotError::Format(e)) if e == "Bad dense_limit"));
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::fmt::Display;
+use super::{Int, IntcodeMachine, Input, Output, Word, decode};
+use super::disasm::{Line, decode_line};
+use super::memory::Words;
+
+// Tracers see every instruction a machine executes.  They are shared
+// (so they can be looked at while or after the machine runs) and clones
+// of a machine feed the same tracers.  A machine with no tracers doesn't
+// do any of the extra work.  Instructions the disassembler can't decode,
+// such as custom opcodes, show as data but still have their reads and
+// writes.
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct TraceEntry<W = Int> {
+    pub pc: usize,
+    pub line: Line<W>,
+    // Values of the operands read, in order
+    pub reads: Vec<W>,
+    // Addresses written and the values written there
+    pub writes: Vec<(usize, W)>,
+    pub next_pc: usize,
+}
+
+pub trait Tracer<W = Int> {
+    fn trace(&mut self, entry: &TraceEntry<W>);
+}
+
+pub type SharedTracer<W = Int> = Arc<Mutex<dyn Tracer<W> + Send>>;
+
+// What the instruction being traced actually read and wrote, including
+// device reads and the operands of custom opcodes.  Reads happen through
+// `&self`, hence the lock.
+pub(super) struct TraceLog<W> {
+    reads: Mutex<Vec<W>>,
+    pub(super) writes: Vec<(usize, W)>,
+}
+
+impl<W> Default for TraceLog<W> {
+    fn default() -> Self {
+        TraceLog { reads: Mutex::new(Vec::new()), writes: Vec::new() }
+    }
+}
+
+// A clone isn't in the middle of an instruction.
+impl<W> Clone for TraceLog<W> {
+    fn clone(&self) -> Self {
+        Default::default()
+    }
+}
+
+impl<W, F: FnMut(&TraceEntry<W>)> Tracer<W> for F {
+    fn trace(&mut self, entry: &TraceEntry<W>) {
+        self(entry)
+    }
+}
+
+// Writes one line per instruction: the pc, the instruction, the values
+// it read and what it wrote.
+pub struct TraceWriter<W> {
+    writer: W,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(writer: W) -> TraceWriter<W> {
+        TraceWriter { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write, V: Display> Tracer<V> for TraceWriter<W> {
+    fn trace(&mut self, entry: &TraceEntry<V>) {
+        let mut line = format!("{:6}  {:<28}", entry.pc, entry.line.to_string());
+        if !entry.reads.is_empty() {
+            let reads: Vec<_> = entry.reads.iter().map(|v| v.to_string()).collect();
+            line.push_str(&format!(" ; {}", reads.join(", ")));
+        }
+        for (addr, val) in &entry.writes {
+            line.push_str(&format!(" ; [{}]={}", addr, val));
+        }
+        let _ = writeln!(self.writer, "{}", line.trim_end());
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Loop {
+    pub start: usize,
+    pub end: usize,
+    // Number of times the jump back to the start was taken
+    pub iterations: u64,
+    // Instructions executed between start and end, inclusive
+    pub cycles: u64,
+}
+
+// Per-address hit counts, and loops found from backward jumps.
+#[derive(Debug,Clone,Default)]
+pub struct Profile {
+    hits: HashMap<usize, u64>,
+    cycles: u64,
+    back_jumps: HashMap<(usize, usize), u64>,
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Default::default()
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn hits(&self, addr: usize) -> u64 {
+        self.hits.get(&addr).cloned().unwrap_or(0)
+    }
+
+    // The most executed addresses, most first.
+    pub fn hottest(&self, count: usize) -> Vec<(usize, u64)> {
+        let mut result: Vec<_> = self.hits.iter().map(|(&addr, &hits)| (addr, hits)).collect();
+        result.sort_unstable_by_key(|&(addr, hits)| (std::cmp::Reverse(hits), addr));
+        result.truncate(count);
+        result
+    }
+
+    // The loops with the most cycles spent in them, most first.
+    pub fn hottest_loops(&self, count: usize) -> Vec<Loop> {
+        let mut result: Vec<_> = self.back_jumps.iter().map(|(&(end, start), &iterations)| {
+            let cycles = self.hits.iter()
+                             .filter(|(&addr, _)| addr >= start && addr <= end)
+                             .map(|(_, &hits)| hits)
+                             .sum();
+            Loop { start, end, iterations, cycles }
+        }).collect();
+        result.sort_unstable_by_key(|l| (std::cmp::Reverse(l.cycles), l.start, l.end));
+        result.truncate(count);
+        result
+    }
+
+    // A summary of the `count` hottest addresses and loops, with the
+    // instructions from `mem`.
+    pub fn report<M: Words + ?Sized>(&self, mem: &M, count: usize) -> String {
+        let mut result = format!("{} cycles\n\nHottest instructions:\n", self.cycles);
+        for (addr, hits) in self.hottest(count) {
+            result.push_str(&format!("{:>12} {:6}  {}\n", hits, addr, decode_line(mem, addr)));
+        }
+        result.push_str("\nHottest loops:\n");
+        for l in self.hottest_loops(count) {
+            result.push_str(&format!("{:>12} {:6}-{:<6} {} iterations\n",
+                                     l.cycles, l.start, l.end, l.iterations));
+        }
+        result
+    }
+}
+
+impl<W> Tracer<W> for Profile {
+    fn trace(&mut self, entry: &TraceEntry<W>) {
+        *self.hits.entry(entry.pc).or_insert(0) += 1;
+        self.cycles += 1;
+        let jump = matches!(&entry.line, Line::Insn { insn, .. } if matches!(insn.op.opcode, 5 | 6));
+        if jump && entry.next_pc <= entry.pc {
+            *self.back_jumps.entry((entry.pc, entry.next_pc)).or_insert(0) += 1;
+        }
+    }
+}
+
+impl<I: Input<W>, O: Output<W>, W: Word> IntcodeMachine<I, O, W> {
+    pub fn add_tracer(&mut self, tracer: SharedTracer<W>) {
+        self.tracers.push(tracer);
+    }
+
+    pub fn clear_tracers(&mut self) {
+        self.tracers.clear();
+    }
+
+    // The instruction at pc, as far as the disassembler can tell.
+    pub(super) fn begin_trace(&mut self) -> Line<W> {
+        let pc = self.pc;
+        let value = self.data.get(pc);
+        self.trace_log = Some(TraceLog::default());
+        match value.to_int().and_then(decode) {
+            Some(insn) if pc.checked_add(insn.size()).is_some() => {
+                let args = self.data.read(pc + 1, insn.op.params);
+                Line::Insn { addr: pc, insn, args }
+            }
+            _ => Line::Data { addr: pc, value },
+        }
+    }
+
+    pub(super) fn end_trace(&mut self, pc: usize, line: Line<W>) {
+        let log = self.trace_log.take().unwrap_or_default();
+        let reads = log.reads.into_inner().unwrap_or_else(|e| e.into_inner());
+        let entry = TraceEntry { pc, line, reads, writes: log.writes, next_pc: self.pc };
+        for tracer in &self.tracers {
+            if let Ok(mut tracer) = tracer.lock() {
+                tracer.trace(&entry);
+            }
+        }
+    }
+
+    pub(super) fn note_read(&self, val: &W) {
+        if let Some(log) = &self.trace_log {
+            if let Ok(mut reads) = log.reads.lock() {
+                reads.push(val.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::asm::assemble;
+
+    // Sums 3 + 2 + 1 by counting n down to zero.
+    const COUNTDOWN: &str = r#"
+                add  #3, #0, n
+        loop:   add  total, n, total
+                add  n, #-1, n
+                jt   n, #loop
+                out  total
+                hlt
+        n:      data 0
+        total:  data 0
+    "#;
+
+    #[test]
+    fn test_trace() {
+        let mut machine = IntcodeMachine::new(&assemble(COUNTDOWN).unwrap());
+        let writer = Arc::new(Mutex::new(TraceWriter::new(Vec::new())));
+        machine.add_tracer(writer.clone());
+        machine.run_until_halt().unwrap();
+        assert_eq!(machine.get_outputs(), &[6]);
+
+        let text = String::from_utf8(writer.lock().unwrap().writer.clone()).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 12);
+        assert_eq!(lines[0], "     0  add #3, #0, 18               ; 3, 0 ; [18]=3");
+        assert_eq!(lines[4], "     4  add 19, 18, 19               ; 3, 2 ; [19]=5");
+        assert_eq!(lines[9], "    12  jt  18, #4                   ; 0, 4");
+        assert_eq!(lines[11], "    17  hlt");
+    }
+
+    // The reads and writes come from running the instruction, so they
+    // include devices and custom opcodes.
+    #[test]
+    fn test_recorded() {
+        let mut program = vec![1101, 5, 0, 100, 1001, 100, 1, 20, 10, 20, 100, 21, 99];
+        program.resize(22, 0);
+        for cached in [false, true] {
+            let mut machine = IntcodeMachine::new(&program);
+            machine.set_decode_cache(cached);
+            let screen = Arc::new(Mutex::new(super::super::device::Screen::new(2, 1).unwrap()));
+            assert!(machine.attach_device(100..102, screen));
+            assert!(machine.define_opcode(10, "sub", 3, |call| {
+                let val = call.read(0)? - call.read(1)?;
+                call.write(2, val)
+            }));
+            let entries = Arc::new(Mutex::new(Vec::new()));
+            let log = entries.clone();
+            machine.add_tracer(Arc::new(Mutex::new(move |e: &TraceEntry| log.lock().unwrap().push(e.clone()))));
+            machine.run_until_halt().unwrap();
+
+            let entries = entries.lock().unwrap();
+            let summary: Vec<_> = entries.iter().map(|e| (e.reads.clone(), e.writes.clone())).collect();
+            assert_eq!(summary, vec![(vec![5, 0], vec![(100, 5)]),
+                                     (vec![5, 1], vec![(20, 6)]),
+                                     (vec![6, 5], vec![(21, 1)]),
+                                     (vec![], vec![])]);
+            assert_eq!(entries[2].line, Line::Data { addr: 8, value: 10 });
+        }
+    }
+
+    #[test]
+    fn test_profile() {
+        let mut machine = IntcodeMachine::new(&assemble(COUNTDOWN).unwrap());
+        let profile = Arc::new(Mutex::new(Profile::new()));
+        machine.add_tracer(profile.clone());
+        machine.run_until_halt().unwrap();
+
+        let profile = profile.lock().unwrap();
+        assert_eq!(profile.cycles(), 12);
+        assert_eq!(profile.hits(4), 3);
+        assert_eq!(profile.hits(0), 1);
+        assert_eq!(profile.hottest(2), vec![(4, 3), (8, 3)]);
+        assert_eq!(profile.hottest_loops(5),
+                   vec![Loop { start: 4, end: 12, iterations: 2, cycles: 9 }]);
+        assert!(profile.report(machine.memory(), 3).contains("  4-12     2 iterations"));
+    }
+}
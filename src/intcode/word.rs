@@ -0,0 +1,161 @@
+use std::fmt::{Debug, Display};
+use num_bigint::{BigInt, Sign};
+use super::Int;
+
+// The type of a machine's memory words and registers.  Addresses are
+// always usize; a word which doesn't fit one can't be used as an address.
+// Instruction words must fit in an Int to be decoded.
+
+pub trait Word: Clone + Debug + Display + Default + PartialEq + PartialOrd + 'static {
+    fn from_int(val: Int) -> Self;
+    fn to_int(&self) -> Option<Int>;
+    fn to_usize(&self) -> Option<usize>;
+    fn is_zero(&self) -> bool;
+    fn is_negative(&self) -> bool;
+    // None on overflow
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+    fn checked_mul(&self, other: &Self) -> Option<Self>;
+}
+
+macro_rules! primitive_word {
+    ($t:ty) => {
+        impl Word for $t {
+            fn from_int(val: Int) -> Self {
+                val as $t
+            }
+
+            fn to_int(&self) -> Option<Int> {
+                Int::try_from(*self).ok()
+            }
+
+            fn to_usize(&self) -> Option<usize> {
+                usize::try_from(*self).ok()
+            }
+
+            fn is_zero(&self) -> bool {
+                *self == 0
+            }
+
+            fn is_negative(&self) -> bool {
+                *self < 0
+            }
+
+            fn checked_add(&self, other: &Self) -> Option<Self> {
+                <$t>::checked_add(*self, *other)
+            }
+
+            fn checked_mul(&self, other: &Self) -> Option<Self> {
+                <$t>::checked_mul(*self, *other)
+            }
+        }
+    }
+}
+
+primitive_word!(isize);
+primitive_word!(i64);
+primitive_word!(i128);
+
+// Arbitrary precision: arithmetic never overflows.
+impl Word for BigInt {
+    fn from_int(val: Int) -> Self {
+        BigInt::from(val)
+    }
+
+    fn to_int(&self) -> Option<Int> {
+        Int::try_from(self).ok()
+    }
+
+    fn to_usize(&self) -> Option<usize> {
+        usize::try_from(self).ok()
+    }
+
+    fn is_zero(&self) -> bool {
+        self.sign() == Sign::NoSign
+    }
+
+    fn is_negative(&self) -> bool {
+        self.sign() == Sign::Minus
+    }
+
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        Some(self + other)
+    }
+
+    fn checked_mul(&self, other: &Self) -> Option<Self> {
+        Some(self * other)
+    }
+}
+
+pub fn widen<W: Word>(data: &[Int]) -> Vec<W> {
+    data.iter().map(|&val| W::from_int(val)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{IntcodeMachine, WideMachine, Error};
+
+    // Squares its input twice.
+    const SQUARES: &[Int] = &[3,13, 2,13,13,13, 2,13,13,13, 4,13, 99, 0];
+
+    #[test]
+    fn test_overflow() {
+        let mut machine = IntcodeMachine::new(SQUARES);
+        machine.send_input(1 << 20);
+        assert_eq!(machine.run_until_halt(), Err(Error::Overflow { pc: 6, insn: 2 }));
+
+        let mut machine = WideMachine::<i64>::wide(SQUARES);
+        machine.send_input(1 << 20);
+        assert_eq!(machine.run_until_halt(), Err(Error::Overflow { pc: 6, insn: 2 }));
+
+        let mut machine = WideMachine::<i128>::wide(SQUARES);
+        machine.send_input(1 << 20);
+        machine.run_until_halt().unwrap();
+        assert_eq!(machine.get_outputs(), &[1 << 80]);
+    }
+
+    #[test]
+    fn test_bigint() {
+        let mut machine = WideMachine::<BigInt>::wide(SQUARES);
+        machine.send_input(BigInt::from(1) << 100);
+        machine.run_until_halt().unwrap();
+        assert_eq!(machine.take_outputs(), vec![BigInt::from(1) << 400]);
+
+        let mut cached = WideMachine::<BigInt>::wide(SQUARES);
+        cached.set_decode_cache(true);
+        cached.send_input(BigInt::from(-3));
+        cached.run_until_halt().unwrap();
+        assert_eq!(cached.take_outputs(), vec![BigInt::from(81)]);
+    }
+
+    #[test]
+    fn test_address_too_large() {
+        let mut machine = WideMachine::<i128>::wide(&[1101,1,1,0,99]);
+        machine.set(3, 1 << 100).unwrap();
+        assert_eq!(machine.run_until_halt(),
+                   Err(Error::AddressTooLarge { pc: 0, insn: 1101, addr: 1 << 100 }));
+    }
+
+    #[test]
+    fn test_end_of_memory() {
+        use std::sync::{Arc, Mutex};
+        use super::super::Watch;
+        use super::super::coverage::Coverage;
+        // Writes `out #0` to the last two addresses and jumps there, so
+        // the next instruction would be past the end.
+        let end = usize::MAX as i128 - 1;
+        for (cached, hooked) in [(false, false), (true, false), (false, true)] {
+            let mut machine = WideMachine::<i128>::wide(&[1101,104,0,0, 1105,1,0, 99]);
+            machine.set(3, end).unwrap();
+            machine.set(6, end).unwrap();
+            machine.set_decode_cache(cached);
+            if hooked {
+                machine.add_watchpoint(0, Watch::Write);
+                machine.add_tracer(Arc::new(Mutex::new(Coverage::new())));
+            }
+            assert_eq!(machine.run_until_halt(),
+                       Err(Error::AddressTooLarge { pc: end as usize, insn: 104, addr: end + 2 }));
+            assert!(machine.get_outputs().is_empty());
+        }
+    }
+}